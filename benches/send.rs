@@ -0,0 +1,88 @@
+//! Compares the ways of sending a packet: allocating it with [`wintun::Session::allocate_send_packet`]
+//! and writing into the ring, [`wintun::Session::send_slice`] and
+//! [`wintun::Session::send_buffered`]. The first two busy retry on a full ring. Needs administrator
+//! rights and the wintun driver, run it from an elevated prompt with `cargo bench --bench send`
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use std::{
+    net::Ipv4Addr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+const PACKETS: u32 = 200_000;
+const PACKET_LEN: usize = 1280;
+
+/// An IPv4 UDP packet to the address of the adapter. Its header checksum is left zero, so the
+/// stack discards it right away
+fn test_packet() -> Vec<u8> {
+    let mut packet = vec![0u8; PACKET_LEN];
+    packet[0] = 0x45;
+    packet[2..4].copy_from_slice(&(PACKET_LEN as u16).to_be_bytes());
+    packet[8] = 64;
+    packet[9] = 17;
+    packet[12..16].copy_from_slice(&[10, 28, 22, 2]);
+    packet[16..20].copy_from_slice(&[10, 28, 22, 1]);
+    packet[24..26].copy_from_slice(&((PACKET_LEN - 20) as u16).to_be_bytes());
+    packet
+}
+
+fn send_naive(session: &Arc<wintun::Session>, data: &[u8]) {
+    loop {
+        match session.allocate_send_packet(data.len() as u16) {
+            Ok(mut packet) => {
+                packet.copy_from_slice(data);
+                session.send_packet(packet);
+                return;
+            }
+            Err(wintun::Error::RingFull) => std::hint::spin_loop(),
+            Err(err) => panic!("Send failed: {}", err),
+        }
+    }
+}
+
+fn send_slice(session: &wintun::Session, data: &[u8]) {
+    loop {
+        match session.send_slice(data) {
+            Ok(()) => return,
+            Err(wintun::Error::RingFull) => std::hint::spin_loop(),
+            Err(err) => panic!("Send failed: {}", err),
+        }
+    }
+}
+
+fn report(name: &str, elapsed: Duration) {
+    let per_packet = elapsed.as_nanos() / PACKETS as u128;
+    let rate = PACKETS as f64 / elapsed.as_secs_f64();
+    println!("{:<14} {:>6} ns/packet {:>12.0} packets/s", name, per_packet, rate);
+}
+
+fn main() {
+    let wintun = common::load();
+    let adapter = common::create_adapter(&wintun, "WintunBenchSend");
+    adapter.set_ipv4(Ipv4Addr::new(10, 28, 22, 1), 24).unwrap();
+    let session = Arc::new(adapter.start_session(wintun::MIN_RING_CAPACITY).unwrap());
+    let packet = test_packet();
+
+    let start = Instant::now();
+    for _ in 0..PACKETS {
+        send_naive(&session, &packet);
+    }
+    report("naive", start.elapsed());
+
+    let start = Instant::now();
+    for _ in 0..PACKETS {
+        send_slice(&session, &packet);
+    }
+    report("send_slice", start.elapsed());
+
+    let mut buf = wintun::SendBuffer::with_capacity(PACKET_LEN);
+    buf.set(&packet);
+    let start = Instant::now();
+    for _ in 0..PACKETS {
+        session.send_buffered(&buf).unwrap();
+    }
+    report("send_buffered", start.elapsed());
+}
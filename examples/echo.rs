@@ -0,0 +1,86 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::time::Duration;
+mod misc;
+
+static RUNNING: AtomicBool = AtomicBool::new(true);
+
+const ICMP_ECHO_REQUEST: u8 = 8;
+const ICMP_ECHO_REPLY: u8 = 0;
+
+/// Swaps the source and destination addresses of an IPv4 or IPv6 packet in place. Returns false
+/// for anything else. Checksums stay valid, because swapping two fields does not change the sum
+fn swap_addresses(packet: &mut [u8]) -> bool {
+    let (src, len) = match packet.first().map(|b| b >> 4) {
+        Some(4) => (12, 4),
+        Some(6) => (8, 16),
+        _ => return false,
+    };
+    if packet.len() < src + 2 * len {
+        return false;
+    }
+    let (head, tail) = packet.split_at_mut(src + len);
+    head[src..].swap_with_slice(&mut tail[..len]);
+
+    //Turn ICMP echo requests into replies so that pinging any other address in the subnet works
+    let header_len = ((packet[0] & 0x0f) as usize) * 4;
+    if packet[0] >> 4 == 4
+        && packet[9] == 1
+        && packet.len() >= header_len + 4
+        && packet[header_len] == ICMP_ECHO_REQUEST
+    {
+        packet[header_len] = ICMP_ECHO_REPLY;
+        //Incrementally update the checksum for the type field going from 8 to 0
+        let checksum = u16::from_be_bytes([packet[header_len + 2], packet[header_len + 3]]) as u32;
+        let sum = checksum + ((ICMP_ECHO_REQUEST as u32) << 8);
+        let sum = (sum & 0xffff) + (sum >> 16);
+        packet[header_len + 2..header_len + 4].copy_from_slice(&(sum as u16).to_be_bytes());
+    }
+    true
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    dotenvy::dotenv().ok();
+    env_logger::init();
+    let dll_path = misc::get_wintun_bin_relative_path()?;
+    let wintun = unsafe { wintun::load_from_path(dll_path)? };
+
+    let adapter = match wintun::Adapter::open(&wintun, "Echo") {
+        Ok(a) => a,
+        Err(_) => wintun::Adapter::create(&wintun, "Echo", "Example", None)?,
+    };
+    adapter.set_ipv4("10.28.14.1".parse()?, 24)?;
+
+    let session = Arc::new(adapter.start_session(wintun::MAX_RING_CAPACITY)?);
+
+    let echo_session = session.clone();
+    let echo = std::thread::spawn(move || {
+        while RUNNING.load(Ordering::Relaxed) {
+            let Some(mut packet) = echo_session.try_receive()? else {
+                echo_session.wait_readable(Some(Duration::from_millis(100)))?;
+                continue;
+            };
+            if !swap_addresses(&mut packet) {
+                continue;
+            }
+            let mut reply = echo_session.allocate_send_packet(packet.len() as u16)?;
+            reply.copy_from_slice(&packet);
+            reply.send();
+        }
+        Ok::<(), wintun::Error>(())
+    });
+    println!("Echoing packets sent to 10.28.14.0/24, try `ping 10.28.14.2`. Press enter to stop");
+
+    let mut line = String::new();
+    let _ = std::io::stdin().read_line(&mut line);
+    println!("Shutting down session");
+
+    RUNNING.store(false, Ordering::Relaxed);
+    session.shutdown()?;
+    echo.join().map_err(|err| wintun::Error::from(format!("{:?}", err)))??;
+
+    println!("Shutdown complete");
+    Ok(())
+}
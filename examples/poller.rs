@@ -0,0 +1,52 @@
+use polling::{os::iocp::PollerIocpExt, Event, Events, PollMode, Poller};
+use std::{os::windows::io::BorrowedHandle, sync::Arc, time::Duration};
+mod misc;
+
+/// Key of the session's read event in the poller
+const READ_KEY: usize = 1;
+
+/// How many packets to print before exiting
+const PACKETS: usize = 20;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    dotenvy::dotenv().ok();
+    env_logger::init();
+    let dll_path = misc::get_wintun_bin_relative_path()?;
+    let wintun = unsafe { wintun::load_from_path(dll_path)? };
+
+    let adapter = match wintun::Adapter::open(&wintun, "Poller") {
+        Ok(a) => a,
+        Err(_) => wintun::Adapter::create(&wintun, "Poller", "Example", None)?,
+    };
+    let session = Arc::new(adapter.start_session(wintun::MAX_RING_CAPACITY)?);
+
+    let poller = Poller::new()?;
+    let read_event = session.read_wait_event_raw()?;
+    //SAFETY: The read event stays valid until the session ends, and it is removed from the poller
+    //before that below
+    unsafe { poller.add_waitable(read_event, Event::readable(READ_KEY), PollMode::Oneshot)? };
+
+    let mut events = Events::new();
+    let mut printed = 0;
+    while printed < PACKETS {
+        events.clear();
+        poller.wait(&mut events, Some(Duration::from_secs(1)))?;
+        if !events.iter().any(|event| event.key == READ_KEY) {
+            continue;
+        }
+        //The read event is only signaled when the ring goes from empty to non empty, so drain it
+        //completely before re-arming
+        while let Some(packet) = session.try_receive()? {
+            println!("Read packet of {} bytes", packet.bytes().len());
+            printed += 1;
+        }
+        //Re-arming picks up packets that arrived after the ring was drained, since the event is
+        //still signaled then
+        let handle = unsafe { BorrowedHandle::borrow_raw(read_event) };
+        poller.modify_waitable(handle, Event::readable(READ_KEY), PollMode::Oneshot)?;
+    }
+
+    poller.remove_waitable(unsafe { BorrowedHandle::borrow_raw(read_event) })?;
+    session.shutdown()?;
+    Ok(())
+}
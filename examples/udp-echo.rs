@@ -202,7 +202,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 write_pack.bytes_mut().copy_from_slice(ip_packet.as_ref());
 
                 // Send the response packet
-                writer_session.send_packet(write_pack);
+                write_pack.send();
             }
             Ok::<(), Box<dyn std::error::Error>>(())
         };
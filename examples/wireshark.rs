@@ -230,7 +230,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .identifier(42)?
                 .sequence(2)?
                 .build()?;
-            writer_session.send_packet(packet);
+            packet.send();
             std::thread::sleep(std::time::Duration::from_secs(1));
         }
         Ok::<(), packet::Error>(())
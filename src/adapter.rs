@@ -3,30 +3,232 @@
 ///
 /// The [`Adapter::create`] and [`Adapter::open`] functions serve as the entry point to using
 /// wintun functionality
+#[cfg(not(feature = "logging"))]
+use crate::log::noop as log;
 use crate::{
-    error::{Error, OutOfRangeData},
+    error::{Error, OutOfRangeData, Win32Error},
     session,
     util::{self, UnsafeHandle},
     wintun_raw, Wintun,
 };
 use std::{
-    ffi::OsStr,
-    net::{IpAddr, Ipv4Addr},
-    os::windows::prelude::OsStrExt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
     process::Command,
     ptr,
     sync::Arc,
-    sync::OnceLock,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+        Mutex, OnceLock,
+    },
 };
 use windows_sys::{
     core::GUID,
     Win32::{
-        Foundation::FALSE,
-        NetworkManagement::{IpHelper::ConvertLengthToIpv4Mask, Ndis::NET_LUID_LH},
-        System::Threading::CreateEventA,
+        Foundation::{
+            ERROR_ALREADY_EXISTS, ERROR_DUP_NAME, ERROR_INVALID_PARAMETER, ERROR_NOT_FOUND, ERROR_OBJECT_ALREADY_EXISTS,
+        },
+        NetworkManagement::{
+            IpHelper::ConvertLengthToIpv4Mask,
+            Ndis::{
+                IfOperStatusDormant, IfOperStatusDown, IfOperStatusLowerLayerDown, IfOperStatusNotPresent,
+                IfOperStatusTesting, IfOperStatusUp, IF_OPER_STATUS, NET_LUID_LH,
+            },
+        },
+        Networking::WinSock::{ADDRESS_FAMILY, AF_INET, AF_INET6, SOCKADDR_INET},
     },
 };
 
+/// Information about an existing wintun adapter, as returned by [`Adapter::list`] and
+/// [`Adapter::for_each`]
+#[derive(Clone)]
+pub struct AdapterInfo {
+    /// The `Friendly Name` of the adapter
+    pub name: String,
+    pub guid: u128,
+    pub luid: NET_LUID_LH,
+}
+
+/// Operational state of an interface, as reported in the `OperStatus` field of `MIB_IF_ROW2`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OperStatus {
+    /// Packets can be passed, for a wintun adapter this means a session is running
+    Up,
+    Down,
+    Testing,
+    Unknown,
+    /// Waiting for some external event
+    Dormant,
+    /// A component of the interface, typically its driver, is missing
+    NotPresent,
+    /// Down because an interface it runs on top of is down
+    LowerLayerDown,
+}
+
+impl OperStatus {
+    #[allow(non_upper_case_globals)]
+    fn from_raw(status: IF_OPER_STATUS) -> Self {
+        match status {
+            IfOperStatusUp => Self::Up,
+            IfOperStatusDown => Self::Down,
+            IfOperStatusTesting => Self::Testing,
+            IfOperStatusDormant => Self::Dormant,
+            IfOperStatusNotPresent => Self::NotPresent,
+            IfOperStatusLowerLayerDown => Self::LowerLayerDown,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+/// An IP version, selecting which of an interface's per family settings to change
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddressFamily {
+    V4,
+    V6,
+}
+
+impl AddressFamily {
+    fn to_raw(self) -> ADDRESS_FAMILY {
+        match self {
+            Self::V4 => AF_INET,
+            Self::V6 => AF_INET6,
+        }
+    }
+}
+
+/// Status of an adapter, as returned by [`Adapter::status`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AdapterStatus {
+    pub oper_status: OperStatus,
+    /// The MTU of the interface in bytes
+    pub mtu: u32,
+    /// Transmit link speed in bits per second
+    pub transmit_link_speed: u64,
+    /// Receive link speed in bits per second
+    pub receive_link_speed: u64,
+}
+
+/// Cumulative traffic counters of an adapter, as returned by [`Adapter::statistics`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InterfaceStats {
+    /// Bytes received by the interface, which are the packets sent through a session
+    pub rx_bytes: u64,
+    /// Bytes sent by the interface, which are the packets received through a session
+    pub tx_bytes: u64,
+    /// Unicast packets received by the interface
+    pub rx_packets: u64,
+    /// Unicast packets sent by the interface
+    pub tx_packets: u64,
+}
+
+/// An IPv4 address assigned by [`Adapter::scoped_ipv4`], which is removed from the adapter again
+/// when this guard is dropped
+///
+/// Removal also runs while unwinding from a panic, so a failing test does not leak the address.
+/// Errors while removing are only logged, use [`IpGuard::remove`] to handle them
+#[must_use = "the address is removed again when the guard is dropped"]
+pub struct IpGuard<'a> {
+    adapter: &'a Adapter,
+    address: Ipv4Addr,
+}
+
+impl IpGuard<'_> {
+    /// Returns the address this guard keeps assigned
+    pub fn address(&self) -> Ipv4Addr {
+        self.address
+    }
+
+    /// Removes the address now, returning any error that [`Adapter::remove_ipv4`] reports
+    pub fn remove(self) -> Result<(), Error> {
+        let guard = std::mem::ManuallyDrop::new(self);
+        guard.adapter.remove_ipv4(guard.address)
+    }
+}
+
+impl Drop for IpGuard<'_> {
+    fn drop(&mut self) {
+        if let Err(err) = self.adapter.remove_ipv4(self.address) {
+            log::warn!("Failed to remove scoped address {}: {}", self.address, err);
+        }
+    }
+}
+
+/// Builder for creating an [`Adapter`], optionally starting a session on it straight away
+///
+/// ```no_run
+/// # let wintun = unsafe { wintun::load() }.unwrap();
+/// let (adapter, session) = wintun::AdapterBuilder::new()
+///     .name("Demo")
+///     .pool("Example")
+///     .ring_capacity(0x40_0000)
+///     .create_and_start(&wintun)
+///     .unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct AdapterBuilder {
+    name: String,
+    pool: String,
+    guid: Option<u128>,
+    ring_capacity: u32,
+}
+
+impl Default for AdapterBuilder {
+    fn default() -> Self {
+        Self {
+            name: "Wintun".to_owned(),
+            pool: "Wintun".to_owned(),
+            guid: None,
+            ring_capacity: crate::MAX_RING_CAPACITY,
+        }
+    }
+}
+
+impl AdapterBuilder {
+    /// Creates a builder for an adapter named `Wintun` with tunnel type `Wintun`, a random GUID
+    /// and a ring capacity of [`crate::MAX_RING_CAPACITY`]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the name of the adapter, see [`Adapter::create`]
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Sets the tunnel type of the adapter, see [`Adapter::create`]
+    pub fn pool(mut self, pool: impl Into<String>) -> Self {
+        self.pool = pool.into();
+        self
+    }
+
+    /// Sets the GUID the adapter is created with, see [`Adapter::create`]
+    pub fn guid(mut self, guid: u128) -> Self {
+        self.guid = Some(guid);
+        self
+    }
+
+    /// Sets the ring capacity used by [`AdapterBuilder::create_and_start`], see
+    /// [`Adapter::start_session`]
+    pub fn ring_capacity(mut self, capacity: u32) -> Self {
+        self.ring_capacity = capacity;
+        self
+    }
+
+    /// Creates the adapter. The ring capacity is validated first even though no session is
+    /// started, so an invalid builder fails before anything is created
+    pub fn create(&self, wintun: &Wintun) -> Result<Arc<Adapter>, Error> {
+        check_ring_capacity(self.ring_capacity)?;
+        Adapter::create(wintun, &self.name, &self.pool, self.guid)
+    }
+
+    /// Creates the adapter and starts a session on it with the configured ring capacity
+    pub fn create_and_start(&self, wintun: &Wintun) -> Result<(Arc<Adapter>, session::Session), Error> {
+        let adapter = self.create(wintun)?;
+        let session = adapter.start_session(self.ring_capacity)?;
+        Ok((adapter, session))
+    }
+}
+
 /// Wrapper around a <https://git.zx2c4.com/wintun/about/#wintun_adapter_handle>
 pub struct Adapter {
     adapter: UnsafeHandle<wintun_raw::WINTUN_ADAPTER_HANDLE>,
@@ -34,11 +236,22 @@ pub struct Adapter {
     guid: u128,
     index: u32,
     luid: NET_LUID_LH,
+    /// The tunnel type passed to [`Adapter::create`], or read from the interface description for
+    /// opened adapters
+    tunnel_type: String,
+    /// Set while a session started on this adapter is alive
+    pub(crate) session_active: AtomicBool,
+    /// Set while that session is held as a raw handle given out by [`session::Session::into_raw`]
+    pub(crate) raw_session: AtomicBool,
 }
 
 fn _get_adapter_luid(wintun: &Wintun, adapter: wintun_raw::WINTUN_ADAPTER_HANDLE) -> NET_LUID_LH {
     let mut luid: wintun_raw::NET_LUID = unsafe { std::mem::zeroed() };
-    unsafe { wintun.WintunGetAdapterLUID(adapter, &mut luid as *mut wintun_raw::NET_LUID) };
+    unsafe {
+        wintun
+            .raw()
+            .WintunGetAdapterLUID(adapter, &mut luid as *mut wintun_raw::NET_LUID)
+    };
     unsafe { std::mem::transmute(luid) }
 }
 
@@ -54,33 +267,74 @@ impl Adapter {
     /// which is the human readable name shown in Windows
     ///
     /// Note: This is different from `Adapter Name`, which is a GUID.
+    ///
+    /// Same as [`Adapter::rename`]
     pub fn set_name(&self, name: &str) -> Result<(), Error> {
-        // use command `netsh interface set interface name="oldname" newname="mynewname"`
+        self.rename(name)
+    }
 
-        let args = &[
-            "interface",
-            "set",
-            "interface",
-            &format!("name=\"{}\"", self.get_name()?),
-            &format!("newname=\"{}\"", name),
-        ];
-        util::run_command("netsh", args)?;
+    /// Renames this adapter by setting the alias of its interface, which is found by the GUID of
+    /// the adapter rather than by its current name.
+    ///
+    /// Windows checks that the name is unique while renaming, so [`Error::NameInUse`] is returned
+    /// if another interface is already called `name` without racing other renames
+    pub fn rename(&self, name: &str) -> Result<(), Error> {
+        let name_utf16 = encode_name(name)?;
+        match util::set_connection_name(GUID::from_u128(self.guid), &name_utf16) {
+            Err(err) if err.raw_os_error() == Some(ERROR_DUP_NAME as i32) => Err(Error::NameInUse(name.to_string())),
+            result => result,
+        }
+    }
 
-        Ok(())
+    /// Returns the tunnel type of this adapter, see [`Adapter::pool_name`]
+    pub fn get_tunnel_type(&self) -> Result<String, Error> {
+        Ok(self.tunnel_type.clone())
+    }
+
+    /// Returns the tunnel type, also called pool, of this adapter.
+    ///
+    /// For adapters obtained via [`Adapter::create`] this is the tunnel type that was passed in.
+    /// For opened adapters it is read from the interface description when opening, without the
+    /// ` #2` style suffix Windows adds when several adapters share the same tunnel type. It is
+    /// empty if the description could not be read. Together with [`Adapter::list`] this tells
+    /// which service an adapter belongs to
+    pub fn pool_name(&self) -> &str {
+        &self.tunnel_type
     }
 
+    /// Returns the GUID of this adapter, either the one passed to [`Adapter::create`] or the one
+    /// it was assigned
     pub fn get_guid(&self) -> u128 {
         self.guid
     }
 
+    /// Returns the GUID of this adapter in the braced `{XXXXXXXX-XXXX-XXXX-XXXX-XXXXXXXXXXXX}`
+    /// form used by the registry and firewall rules
+    pub fn guid_string(&self) -> String {
+        util::guid_u128_to_string(self.guid)
+    }
+
+    /// Returns the GUID of this adapter as 16 bytes in the order they appear in
+    /// [`Adapter::guid_string`], which is not the in memory layout of the Win32 `GUID` struct
+    pub fn guid_bytes(&self) -> [u8; 16] {
+        self.guid.to_be_bytes()
+    }
+
     /// Creates a new wintun adapter inside the name `name` with tunnel type `tunnel_type`
     ///
     /// Optionally a GUID can be specified that will become the GUID of this adapter once created.
-    /// Adapters obtained via this function will be able to return their adapter index via
-    /// [`Adapter::get_adapter_index`]
+    /// Passing the same GUID on every run keeps the adapter identity stable across restarts, so
+    /// that things like firewall rules keep matching. When `None`, a random GUID is generated and
+    /// can be read back with [`Adapter::get_guid`] to be persisted. Adapters obtained via this
+    /// function will be able to return their adapter index via [`Adapter::get_adapter_index`]. If
+    /// the driver fails to create the adapter the Win32 error reported by WintunCreateAdapter is
+    /// returned. See [`AdapterBuilder`] for a builder over the same options
+    ///
+    /// Returns [`Error::InvalidName`] if `name` or `tunnel_type` contains a NUL character or is
+    /// longer than [`crate::MAX_ADAPTER_NAME`] allows
     pub fn create(wintun: &Wintun, name: &str, tunnel_type: &str, guid: Option<u128>) -> Result<Arc<Adapter>, Error> {
-        let name_utf16: Vec<_> = name.encode_utf16().chain(std::iter::once(0)).collect();
-        let tunnel_type_utf16: Vec<u16> = tunnel_type.encode_utf16().chain(std::iter::once(0)).collect();
+        let name_utf16 = encode_name(name)?;
+        let tunnel_type_utf16 = encode_name(tunnel_type)?;
 
         let guid = match guid {
             Some(guid) => guid,
@@ -96,10 +350,14 @@ impl Adapter {
         let guid_struct: wintun_raw::GUID = unsafe { std::mem::transmute(GUID::from_u128(guid)) };
         let guid_ptr = &guid_struct as *const wintun_raw::GUID;
 
-        let result = unsafe { wintun.WintunCreateAdapter(name_utf16.as_ptr(), tunnel_type_utf16.as_ptr(), guid_ptr) };
+        let result = unsafe {
+            wintun
+                .raw()
+                .WintunCreateAdapter(name_utf16.as_ptr(), tunnel_type_utf16.as_ptr(), guid_ptr)
+        };
 
         if result.is_null() {
-            Err("Failed to create adapter".into())
+            Err(Win32Error::last().into())
         } else {
             let luid = crate::ffi::alias_to_luid(&name_utf16)?;
             let index = crate::ffi::luid_to_index(&luid)?;
@@ -109,43 +367,162 @@ impl Adapter {
                 guid,
                 index,
                 luid,
+                tunnel_type: tunnel_type.to_string(),
+                session_active: AtomicBool::new(false),
+                raw_session: AtomicBool::new(false),
             }))
         }
     }
 
+    /// Creates a new adapter named `base_name`, or `base_name 2`, `base_name 3` and so on if that
+    /// name is taken, for example by an adapter left behind by a previous run. Returns the adapter
+    /// together with the name it ended up with.
+    ///
+    /// Names that already belong to an interface are skipped, and if another process takes a name
+    /// between that check and creating the adapter the next name is tried. Gives up with
+    /// [`Error::NameInUse`] after 100 names. The adapter gets a random GUID, see [`Adapter::create`]
+    pub fn create_unique(wintun: &Wintun, pool: &str, base_name: &str) -> Result<(Arc<Adapter>, String), Error> {
+        for attempt in 1..=MAX_UNIQUE_NAME_ATTEMPTS {
+            let name = match attempt {
+                1 => base_name.to_owned(),
+                n => format!("{} {}", base_name, n),
+            };
+            if crate::ffi::alias_to_luid(&encode_name(&name)?).is_ok() {
+                continue;
+            }
+            match Self::create(wintun, &name, pool, None) {
+                Ok(adapter) => {
+                    //Read the name back in case Windows adjusted it
+                    let name = adapter.get_name().unwrap_or(name);
+                    return Ok((adapter, name));
+                }
+                Err(err) if is_already_exists(&err) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Err(Error::NameInUse(base_name.to_owned()))
+    }
+
     /// Attempts to open an existing wintun interface name `name`.
     ///
-    /// Adapters opened via this call will have an unknown GUID meaning [`Adapter::get_adapter_index`]
-    /// will always fail because knowing the adapter's GUID is required to determine its index.
-    /// Currently a workaround is to delete and re-create a new adapter every time one is needed so
-    /// that it gets created with a known GUID, allowing [`Adapter::get_adapter_index`] to works as
-    /// expected. There is likely a way to get the GUID of our adapter using the Windows Registry
-    /// or via the Win32 API, so PR's that solve this issue are always welcome!
+    /// The GUID, LUID and interface index of the opened adapter are looked up from its name, so
+    /// [`Adapter::get_guid`] and [`Adapter::get_adapter_index`] work the same as for adapters
+    /// obtained via [`Adapter::create`]. The adapter handle is closed once the returned adapter
+    /// is dropped. Names that [`Adapter::create`] rejects return [`Error::InvalidName`] here too
     pub fn open(wintun: &Wintun, name: &str) -> Result<Arc<Adapter>, Error> {
-        let name_utf16: Vec<u16> = OsStr::new(name).encode_wide().chain(std::iter::once(0)).collect();
+        let name_utf16 = encode_name(name)?;
 
         crate::log::set_default_logger_if_unset(wintun);
 
-        let result = unsafe { wintun.WintunOpenAdapter(name_utf16.as_ptr()) };
+        let result = unsafe { wintun.raw().WintunOpenAdapter(name_utf16.as_ptr()) };
 
         if result.is_null() {
-            Err("WintunOpenAdapter failed".into())
+            Err(Win32Error::last().into())
         } else {
             let luid = crate::ffi::alias_to_luid(&name_utf16)?;
             let index = crate::ffi::luid_to_index(&luid)?;
             let guid = crate::ffi::luid_to_guid(&luid)?;
             let guid = unsafe { std::mem::transmute(guid) };
+            let tunnel_type = lookup_tunnel_type(&luid).unwrap_or_else(|err| {
+                log::warn!("Failed to read the tunnel type of adapter {}: {}", name, err);
+                String::new()
+            });
             Ok(Arc::new(Adapter {
                 adapter: UnsafeHandle(result),
                 wintun: wintun.clone(),
                 guid,
                 index,
                 luid,
+                tunnel_type,
+                session_active: AtomicBool::new(false),
+                raw_session: AtomicBool::new(false),
             }))
         }
     }
 
+    /// Opens a wintun adapter by its interface alias, the friendly name shown in the network
+    /// connections panel, for adopting an adapter created by WireGuard or another wintun user.
+    ///
+    /// The alias is resolved with ConvertInterfaceAliasToLuid first, so an unknown alias is
+    /// reported as [`Error::NotFound`] while an interface that exists but is not a wintun adapter
+    /// fails to open. The pool the adapter was created with cannot be queried, so
+    /// [`Adapter::get_tunnel_type`] falls back to the interface description, which may not match it
+    pub fn open_by_alias(wintun: &Wintun, alias: &str) -> Result<Arc<Adapter>, Error> {
+        let alias_utf16 = encode_name(alias)?;
+        //Windows reports an unknown alias as an invalid parameter
+        let luid = crate::ffi::alias_to_luid(&alias_utf16).map_err(|err| match err.raw_os_error() {
+            Some(code) if code as u32 == ERROR_INVALID_PARAMETER || code as u32 == ERROR_NOT_FOUND => {
+                Error::NotFound(alias.to_owned())
+            }
+            _ => err.into(),
+        })?;
+        //Open with the alias as Windows stores it, which may differ in case from the one passed in
+        let name = util::decode_utf16(&crate::ffi::luid_to_alias(&luid)?);
+        Self::open(wintun, &name)
+    }
+
+    /// Lists the existing adapters created with the tunnel type `tunnel_type`, for example to find
+    /// adapters left behind by a previous run.
+    ///
+    /// Adapters are matched by their interface description, which wintun sets to the tunnel type
+    /// (Windows appends ` #2`, ` #3`, etc. when it is shared by several adapters). The adapters are
+    /// returned in the order Windows reports them, which is not guaranteed to be stable
+    pub fn list(tunnel_type: &str) -> Result<Vec<AdapterInfo>, Error> {
+        let mut adapters = vec![];
+        Self::for_each(tunnel_type, |info| {
+            adapters.push(info.clone());
+            true
+        })?;
+        Ok(adapters)
+    }
+
+    /// Calls `f` with each existing adapter created with the tunnel type `tunnel_type`, stopping
+    /// as soon as `f` returns false. Adapters are matched the same way as in [`Adapter::list`].
+    ///
+    /// Use this instead of [`Adapter::list`] when searching for a single adapter. A panic in `f`
+    /// stops the enumeration and is resumed once the interface table has been freed
+    pub fn for_each<F>(tunnel_type: &str, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(&AdapterInfo) -> bool,
+    {
+        util::for_each_if_row(|row| {
+            let description = util::decode_utf16(&row.Description);
+            if strip_instance_suffix(&description) != tunnel_type {
+                return true;
+            }
+            f(&AdapterInfo {
+                name: util::decode_utf16(&row.Alias),
+                guid: util::win_guid_to_u128(&row.InterfaceGuid),
+                luid: row.InterfaceLuid,
+            })
+        })?;
+        Ok(())
+    }
+
+    /// Deletes the wintun adapter named `name` if it exists, without needing to start a session on
+    /// it. Returns Ok(false) if wintun has no adapter with this name.
+    ///
+    /// Useful for cleaning up an adapter left behind by a process that crashed before it could
+    /// close it. Closing an adapter that was opened rather than created does not remove it, so the
+    /// device is removed directly, which requires administrative privileges. The adapter is opened
+    /// through wintun first and the device is checked to be a wintun device before it is removed,
+    /// so other interfaces are never touched
+    pub fn delete_by_name(wintun: &Wintun, name: &str) -> Result<bool, Error> {
+        let adapter = match Self::open(wintun, name) {
+            Ok(adapter) => adapter,
+            Err(err) if is_not_found(&err) => return Ok(false),
+            Err(err) => return Err(err),
+        };
+        let guid = GUID::from_u128(adapter.get_guid());
+        //Close our handle so it does not keep the device busy while it is removed
+        drop(adapter);
+        Ok(util::remove_net_device(&guid)?)
+    }
+
     /// Delete an adapter, consuming it in the process
+    ///
+    /// Every session keeps its adapter alive, so an adapter with a running session is only closed
+    /// once that session is dropped as well
     pub fn delete(self) -> Result<(), Error> {
         //Dropping an adapter closes it
         drop(self);
@@ -158,50 +535,202 @@ impl Adapter {
     ///
     /// Capacity is the size in bytes of the ring buffer used internally by the driver. Must be
     /// a power of two between [`crate::MIN_RING_CAPACITY`] and [`crate::MAX_RING_CAPACITY`] inclusive.
+    /// This is checked before calling into the driver, see [`crate::is_valid_ring_capacity`].
+    ///
+    /// Wintun supports only one session per adapter at a time, so [`Error::SessionAlreadyActive`]
+    /// is returned while a session started on this adapter is still alive. A new session can be
+    /// started once the previous one is dropped. Sessions started by other processes or through
+    /// another [`Adapter`] opened on the same interface are not tracked and fail in the driver as
+    /// before
     pub fn start_session(self: &Arc<Self>, capacity: u32) -> Result<session::Session, Error> {
-        let range = crate::MIN_RING_CAPACITY..=crate::MAX_RING_CAPACITY;
-        if !range.contains(&capacity) {
-            return Err(Error::CapacityOutOfRange(OutOfRangeData { range, value: capacity }));
-        }
-        if !capacity.is_power_of_two() {
-            return Err(Error::CapacityNotPowerOfTwo(capacity));
+        check_ring_capacity(capacity)?;
+        if self
+            .session_active
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            return Err(Error::SessionAlreadyActive);
         }
+        //Allow a new session again if starting this one fails
+        let reset_active = |err: Error| {
+            self.session_active.store(false, Ordering::Release);
+            err
+        };
 
-        let result = unsafe { self.wintun.WintunStartSession(self.adapter.0, capacity) };
+        //Manual reset so that the event wakes every blocking reader, not just the first one. Created
+        //before the session so that a failure here does not leave a session behind
+        let shutdown_event = util::OwnedEvent::new_manual_reset().map_err(|e| reset_active(e.into()))?;
+        let result = unsafe { self.wintun.raw().WintunStartSession(self.adapter.0, capacity) };
 
         if result.is_null() {
-            Err("WintunStartSession failed".into())
+            Err(reset_active(Win32Error::last().into()))
         } else {
-            let shutdown_event = unsafe { CreateEventA(std::ptr::null_mut(), FALSE, FALSE, std::ptr::null_mut()) };
-            Ok(session::Session {
-                session: UnsafeHandle(result),
-                wintun: self.wintun.clone(),
-                read_event: OnceLock::new(),
-                shutdown_event,
-                adapter: Arc::clone(self),
-            })
+            Ok(self.wrap_session(result, capacity, shutdown_event))
+        }
+    }
+
+    /// Wraps a session handle started on this adapter. The caller must have set `session_active`
+    pub(crate) fn wrap_session(
+        self: &Arc<Self>,
+        handle: wintun_raw::WINTUN_SESSION_HANDLE,
+        capacity: u32,
+        shutdown_event: util::OwnedEvent,
+    ) -> session::Session {
+        session::Session {
+            session: UnsafeHandle(handle),
+            wintun: self.wintun.clone(),
+            read_event: OnceLock::new(),
+            shutdown_state: Arc::new(session::ShutdownState::new(shutdown_event)),
+            adapter: Arc::clone(self),
+            peeked: Mutex::new(None),
+            capacity,
+            pending_receives: AtomicUsize::new(0),
+            max_receive_retries: AtomicU32::new(session::DEFAULT_RECEIVE_RETRIES),
+            spin_count: AtomicU32::new(session::DEFAULT_SPIN_COUNT),
+            next_seq: AtomicU64::new(0),
+            #[cfg(feature = "metrics")]
+            metrics: Default::default(),
         }
     }
 
-    /// Returns the Win32 LUID for this adapter
+    /// Returns the Win32 LUID for this adapter, as reported by WintunGetAdapterLUID when the
+    /// adapter was created or opened.
+    ///
+    /// The LUID can be passed to the IP Helper functions such as `ConvertInterfaceLuidToIndex` or
+    /// `CreateUnicastIpAddressEntry`. It only identifies this interface for as long as the adapter
+    /// exists, so it should not be used after the adapter is dropped or deleted.
+    ///
+    /// ```no_run
+    /// # let wintun = unsafe { wintun::load() }.unwrap();
+    /// # let adapter = wintun::Adapter::open(&wintun, "Demo").unwrap();
+    /// use windows_sys::Win32::NetworkManagement::IpHelper::ConvertInterfaceLuidToIndex;
+    ///
+    /// let luid = adapter.get_luid();
+    /// let mut index = 0;
+    /// assert_eq!(unsafe { ConvertInterfaceLuidToIndex(&luid, &mut index) }, 0);
+    /// assert_eq!(index, adapter.get_adapter_index().unwrap());
+    /// ```
     pub fn get_luid(&self) -> NET_LUID_LH {
         self.luid
     }
 
-    /// Set `MTU` of this adapter
+    /// Set `MTU` of this adapter for both IPv4 and IPv6, using SetIpInterfaceEntry.
+    ///
+    /// A family that is not enabled on this adapter is skipped. Requires administrative privileges
     pub fn set_mtu(&self, mtu: usize) -> Result<(), Error> {
-        let name = self.get_name()?;
-        Ok(util::set_adapter_mtu(&name, mtu)?)
+        let mtu = u32::try_from(mtu).map_err(|_| format!("Invalid MTU {}", mtu))?;
+        let mut set_any = false;
+        for family in [AF_INET, AF_INET6] {
+            match crate::ffi::set_ip_interface_mtu(&self.luid, family, mtu) {
+                Ok(()) => set_any = true,
+                Err(err) if err.raw_os_error() == Some(ERROR_NOT_FOUND as i32) => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        if !set_any {
+            return Err("Neither IPv4 nor IPv6 is enabled on this adapter".into());
+        }
+        Ok(())
+    }
+
+    /// Sets the interface metric of this adapter for `family`, using SetIpInterfaceEntry. Requires
+    /// administrative privileges.
+    ///
+    /// Routes through the interface with the lowest metric are preferred, so a low metric makes
+    /// the tunnel win over the physical interface for default routes. This also turns off
+    /// `UseAutomaticMetric`, since Windows ignores the configured metric while it is on. Note that
+    /// a metric of 0 is not a way back to automatic metrics, it is passed to Windows as is. IPv4
+    /// and IPv6 keep separate metrics
+    pub fn set_metric(&self, metric: u32, family: AddressFamily) -> Result<(), Error> {
+        Ok(crate::ffi::set_ip_interface_metric(
+            &self.luid,
+            family.to_raw(),
+            metric,
+        )?)
+    }
+
+    /// Enables or disables forwarding of IP packets for `family` on this adapter, using
+    /// SetIpInterfaceEntry. Requires administrative privileges.
+    ///
+    /// With forwarding enabled Windows routes packets arriving through the tunnel out of other
+    /// interfaces, which a gateway needs. The other interfaces involved must allow forwarding as
+    /// well, or the system wide `IPEnableRouter` setting must be on, for packets to pass through
+    pub fn set_forwarding(&self, enabled: bool, family: AddressFamily) -> Result<(), Error> {
+        Ok(crate::ffi::set_ip_interface_forwarding(
+            &self.luid,
+            family.to_raw(),
+            enabled,
+        )?)
+    }
+
+    /// Sets the administrative state of this adapter, using SetIfEntry. Requires administrative
+    /// privileges.
+    ///
+    /// While down the interface is treated as disconnected and its routes are not used, but its
+    /// configured addresses and routes are kept, so a tunnel can be paused during a reconnect and
+    /// resumed with `set_up(true)`. Windows does not document any difference in this behaviour
+    /// between Windows 10 and 11. The state is not persisted, so a recreated adapter starts up
+    pub fn set_up(&self, up: bool) -> Result<(), Error> {
+        Ok(crate::ffi::set_if_admin_status(self.index, up)?)
     }
 
-    /// Returns `MTU` of this adapter
+    /// Returns the operational state, MTU and link speeds of this adapter, using GetIfEntry2.
+    ///
+    /// Wintun is a virtual adapter, so the link speeds are a fixed value reported by the driver
+    /// rather than a measured rate. The MTU is the one of the interface itself, the IPv4 and IPv6
+    /// MTUs used for routing are returned by [`Adapter::get_mtu`]
+    pub fn status(&self) -> Result<AdapterStatus, Error> {
+        let row = crate::ffi::get_if_entry(&self.luid)?;
+        Ok(AdapterStatus {
+            oper_status: OperStatus::from_raw(row.OperStatus),
+            mtu: row.Mtu,
+            transmit_link_speed: row.TransmitLinkSpeed,
+            receive_link_speed: row.ReceiveLinkSpeed,
+        })
+    }
+
+    /// Returns the traffic counters of this adapter, using GetIfEntry2.
+    ///
+    /// The counters are kept by Windows since the interface came up. They start again from zero
+    /// when the adapter is recreated, so take the difference between two calls on the same
+    /// adapter to measure throughput
+    pub fn statistics(&self) -> Result<InterfaceStats, Error> {
+        let row = crate::ffi::get_if_entry(&self.luid)?;
+        Ok(InterfaceStats {
+            rx_bytes: row.InOctets,
+            tx_bytes: row.OutOctets,
+            rx_packets: row.InUcastPkts,
+            tx_packets: row.OutUcastPkts,
+        })
+    }
+
+    /// Returns `MTU` of this adapter, as seen by IPv4 or by IPv6 if IPv4 is not enabled
     pub fn get_mtu(&self) -> Result<usize, Error> {
-        let luid = self.get_luid();
-        Ok(util::get_adapter_mtu(&luid)?)
+        Ok(interface_mtu(&self.luid)? as usize)
+    }
+
+    /// Sets the MTU of this adapter to the MTU of the interface with `underlay_luid`, which the
+    /// encapsulated packets are sent over, minus `overhead` bytes of encapsulation. Returns the
+    /// MTU that was set.
+    ///
+    /// The underlay MTU is read like [`Adapter::get_mtu`], and the result is clamped to at least
+    /// 1280 bytes, the minimum MTU IPv6 requires, even if the underlay cannot carry that without
+    /// fragmenting. See [`Adapter::set_mtu`] for the other requirements
+    pub fn set_mtu_from_underlay(&self, underlay_luid: NET_LUID_LH, overhead: u32) -> Result<u32, Error> {
+        let mtu = interface_mtu(&underlay_luid)?
+            .saturating_sub(overhead)
+            .max(MIN_TUNNEL_MTU);
+        self.set_mtu(mtu as usize)?;
+        Ok(mtu)
     }
 
     /// Returns the Win32 interface index of this adapter. Useful for specifying the interface
-    /// when executing `netsh interface ip` commands
+    /// when executing `netsh interface ip` commands, binding sockets to the adapter, or for
+    /// legacy APIs that take an ifIndex rather than a LUID.
+    ///
+    /// The index is looked up with ConvertInterfaceLuidToIndex when the adapter is created or
+    /// opened, so that failures surface there rather than here
+    #[doc(alias = "get_interface_index")]
     pub fn get_adapter_index(&self) -> Result<u32, Error> {
         Ok(self.index)
     }
@@ -254,12 +783,114 @@ impl Adapter {
         Ok(())
     }
 
-    /// Sets the DNS servers for this adapter
+    /// Assigns the IPv4 `address` with the given prefix length to this adapter, using
+    /// CreateUnicastIpAddressEntry.
+    ///
+    /// Unlike [`Adapter::set_address`] this adds the address alongside any existing ones. If the
+    /// address is already assigned with the same prefix length this succeeds without doing anything
+    pub fn set_ipv4(&self, address: Ipv4Addr, prefix_len: u8) -> Result<(), Error> {
+        if prefix_len > 32 {
+            return Err(format!("Invalid IPv4 prefix length {}, must be at most 32", prefix_len).into());
+        }
+        let address = util::ipv4_to_sockaddr_inet(address);
+        Ok(crate::ffi::create_unicast_ip_address(&self.luid, address, prefix_len)?)
+    }
+
+    /// Removes an IPv4 address previously assigned to this adapter, using
+    /// DeleteUnicastIpAddressEntry
+    pub fn remove_ipv4(&self, address: Ipv4Addr) -> Result<(), Error> {
+        let address = util::ipv4_to_sockaddr_inet(address);
+        Ok(crate::ffi::delete_unicast_ip_address(&self.luid, address)?)
+    }
+
+    /// Like [`Adapter::set_ipv4`], but returns a guard that removes the address again when it is
+    /// dropped. Meant for tests and short lived tunnels
+    ///
+    /// Unlike [`Adapter::set_ipv4`] this fails with ERROR_OBJECT_ALREADY_EXISTS if the address is
+    /// already assigned, so the guard never removes an address that it did not add
+    ///
+    /// ```no_run
+    /// # let wintun = unsafe { wintun::load() }.unwrap();
+    /// # let adapter = wintun::Adapter::open(&wintun, "Demo").unwrap();
+    /// {
+    ///     let _ip = adapter.scoped_ipv4("10.28.14.1".parse().unwrap(), 24).unwrap();
+    ///     //10.28.14.1 is assigned until the end of this scope
+    /// }
+    /// ```
+    pub fn scoped_ipv4(&self, address: Ipv4Addr, prefix_len: u8) -> Result<IpGuard<'_>, Error> {
+        if prefix_len > 32 {
+            return Err(format!("Invalid IPv4 prefix length {}, must be at most 32", prefix_len).into());
+        }
+        let sockaddr = util::ipv4_to_sockaddr_inet(address);
+        crate::ffi::add_unicast_ip_address(&self.luid, sockaddr, prefix_len)?;
+        Ok(IpGuard { adapter: self, address })
+    }
+
+    /// Assigns the IPv6 `address` with the given prefix length to this adapter, using
+    /// CreateUnicastIpAddressEntry. The address is added with a scope id of zero.
+    ///
+    /// Like [`Adapter::set_ipv4`] this adds the address alongside any existing ones, so an adapter
+    /// can carry both IPv4 and IPv6 addresses at the same time
+    pub fn set_ipv6(&self, address: Ipv6Addr, prefix_len: u8) -> Result<(), Error> {
+        if prefix_len > 128 {
+            return Err(format!("Invalid IPv6 prefix length {}, must be at most 128", prefix_len).into());
+        }
+        let address = util::ipv6_to_sockaddr_inet(address);
+        Ok(crate::ffi::create_unicast_ip_address(&self.luid, address, prefix_len)?)
+    }
+
+    /// Removes an IPv6 address previously assigned to this adapter, using
+    /// DeleteUnicastIpAddressEntry
+    pub fn remove_ipv6(&self, address: Ipv6Addr) -> Result<(), Error> {
+        let address = util::ipv6_to_sockaddr_inet(address);
+        Ok(crate::ffi::delete_unicast_ip_address(&self.luid, address)?)
+    }
+
+    /// Adds a route that sends traffic for `destination`/`prefix_len` into this adapter, using
+    /// CreateIpForwardEntry2. A `next_hop` of `None` adds an on-link route.
+    ///
+    /// Both IPv4 and IPv6 destinations are supported, but `next_hop` must be of the same family
+    pub fn add_route(
+        &self,
+        destination: IpAddr,
+        prefix_len: u8,
+        next_hop: Option<IpAddr>,
+        metric: u32,
+    ) -> Result<(), Error> {
+        let (destination, next_hop) = route_sockaddrs(destination, prefix_len, next_hop)?;
+        Ok(crate::ffi::create_ip_forward_entry(
+            &self.luid,
+            destination,
+            prefix_len,
+            next_hop,
+            metric,
+        )?)
+    }
+
+    /// Removes a route previously added with [`Adapter::add_route`]
+    pub fn remove_route(&self, destination: IpAddr, prefix_len: u8, next_hop: Option<IpAddr>) -> Result<(), Error> {
+        let (destination, next_hop) = route_sockaddrs(destination, prefix_len, next_hop)?;
+        Ok(crate::ffi::delete_ip_forward_entry(
+            &self.luid,
+            destination,
+            prefix_len,
+            next_hop,
+        )?)
+    }
+
+    /// Sets the DNS servers for this adapter, replacing any previously set IPv4 and IPv6 servers.
+    /// Passing an empty slice clears them.
+    ///
+    /// Uses SetInterfaceDnsSettings, which is available from Windows 10 build 19041, and falls back
+    /// to `netsh` on older versions. Both require administrative privileges
     pub fn set_dns_servers(&self, dns_servers: &[IpAddr]) -> Result<(), Error> {
         let interface = GUID::from_u128(self.get_guid());
-        if let Err(err) = util::set_interface_dns_servers(interface, dns_servers) {
-            log::error!("Failed to set DNS servers in first attempt: {}", err);
-            util::set_adapter_dns_servers(&self.get_name()?, dns_servers)?;
+        let (v4, v6): (Vec<IpAddr>, Vec<IpAddr>) = dns_servers.iter().partition(|addr| addr.is_ipv4());
+        for (servers, ipv6) in [(v4, false), (v6, true)] {
+            if let Err(err) = util::set_interface_dns_servers(interface, &servers, ipv6) {
+                log::error!("Failed to set DNS servers in first attempt: {}", err);
+                util::set_adapter_dns_servers(&self.get_name()?, &servers, ipv6)?;
+            }
         }
         Ok(())
     }
@@ -324,6 +955,30 @@ impl Adapter {
         Ok(adapter_addresses)
     }
 
+    /// Returns the unicast addresses assigned to this adapter together with their prefix lengths,
+    /// using GetUnicastIpAddressTable.
+    ///
+    /// Both IPv4 and IPv6 addresses are returned. This includes the `fe80::/64` link local address
+    /// Windows assigns to every IPv6 enabled interface, so filter those out when comparing against
+    /// the addresses that were configured
+    pub fn addresses(&self) -> Result<Vec<(IpAddr, u8)>, Error> {
+        Ok(crate::ffi::get_unicast_ip_addresses(&self.luid)?)
+    }
+
+    /// Removes the addresses and routes configured on this adapter, for leaving the system clean
+    /// on teardown or after a previous run was killed before it could clean up.
+    ///
+    /// Only manually configured unicast addresses, such as those set with [`Adapter::set_ipv4`],
+    /// and static routes, such as those added with [`Adapter::add_route`], are removed. Windows
+    /// does not record which process added them, so this includes ones other processes configured
+    /// on this adapter the same way. Link local addresses and the routes Windows derives from
+    /// addresses are left to Windows, and DNS servers are cleared separately with
+    /// [`Adapter::set_dns_servers`]. This is best effort: every entry is attempted and the first
+    /// failure is returned afterwards. Requires administrative privileges
+    pub fn purge_config(&self) -> Result<(), Error> {
+        Ok(crate::ffi::purge_ip_config(&self.luid)?)
+    }
+
     /// Returns the gateway addresses of this adapter, including IPv4 and IPv6 addresses
     pub fn get_gateways(&self) -> Result<Vec<IpAddr>, Error> {
         let name = util::guid_to_win_style_string(&GUID::from_u128(self.guid))?;
@@ -372,7 +1027,7 @@ impl Adapter {
                                 let mut mask = 0_u32;
                                 match unsafe { ConvertLengthToIpv4Mask(masklength as u32, &mut mask as *mut u32) } {
                                     0 => {}
-                                    err => return Err(std::io::Error::from_raw_os_error(err as i32).into()),
+                                    err => return Err(Win32Error::new(err).into()),
                                 }
                                 subnet_mask = Some(IpAddr::V4(Ipv4Addr::from(mask.to_le_bytes())));
                             }
@@ -394,11 +1049,124 @@ impl Adapter {
     }
 }
 
+/// Validates a route and converts its destination and next hop for the IP Helper API
+fn route_sockaddrs(
+    destination: IpAddr,
+    prefix_len: u8,
+    next_hop: Option<IpAddr>,
+) -> Result<(SOCKADDR_INET, SOCKADDR_INET), Error> {
+    let max_prefix_len = if destination.is_ipv4() { 32 } else { 128 };
+    if prefix_len > max_prefix_len {
+        return Err(format!("Invalid prefix length {} for {}", prefix_len, destination).into());
+    }
+    let next_hop = match next_hop {
+        Some(next_hop) if next_hop.is_ipv4() != destination.is_ipv4() => {
+            return Err(format!("Next hop {} is not in the same family as {}", next_hop, destination).into());
+        }
+        Some(next_hop) => next_hop,
+        //The unspecified address makes the route on-link
+        None if destination.is_ipv4() => Ipv4Addr::UNSPECIFIED.into(),
+        None => Ipv6Addr::UNSPECIFIED.into(),
+    };
+    Ok((
+        util::ipaddr_to_sockaddr_inet(destination),
+        util::ipaddr_to_sockaddr_inet(next_hop),
+    ))
+}
+
+/// Encodes `name` as a zero terminated UTF-16 string for wintun, rejecting names the driver would
+/// truncate or misread
+fn encode_name(name: &str) -> Result<Vec<u16>, Error> {
+    let encoded: Vec<u16> = name.encode_utf16().chain(std::iter::once(0)).collect();
+    if encoded[..encoded.len() - 1].contains(&0) {
+        return Err(Error::InvalidName(format!("{:?} contains a NUL character", name)));
+    }
+    if encoded.len() > crate::MAX_ADAPTER_NAME {
+        let msg = format!("{:?} is longer than {} characters", name, crate::MAX_ADAPTER_NAME - 1);
+        return Err(Error::InvalidName(msg));
+    }
+    Ok(encoded)
+}
+
+#[test]
+fn test_encode_name() {
+    assert_eq!(encode_name("Demo").unwrap(), [68, 101, 109, 111, 0]);
+    assert!(encode_name(&"a".repeat(crate::MAX_ADAPTER_NAME - 1)).is_ok());
+    assert!(matches!(encode_name("De\0mo"), Err(Error::InvalidName(_))));
+    assert!(matches!(
+        encode_name(&"a".repeat(crate::MAX_ADAPTER_NAME)),
+        Err(Error::InvalidName(_))
+    ));
+}
+
+/// Strips the ` #2`, ` #3`, etc. suffix Windows appends to the description of adapters sharing the
+/// same tunnel type
+fn strip_instance_suffix(description: &str) -> &str {
+    match description.rsplit_once(" #") {
+        Some((tunnel_type, n)) if !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()) => tunnel_type,
+        _ => description,
+    }
+}
+
+/// Reads the tunnel type of the adapter with `luid` from its interface description
+fn lookup_tunnel_type(luid: &NET_LUID_LH) -> Result<String, Error> {
+    let luid = unsafe { luid.Value };
+    let mut tunnel_type = None;
+    util::for_each_if_row(|row| {
+        if unsafe { row.InterfaceLuid.Value } == luid {
+            let description = util::decode_utf16(&row.Description);
+            tunnel_type = Some(strip_instance_suffix(&description).to_owned());
+            return false;
+        }
+        true
+    })?;
+    Ok(tunnel_type.ok_or("Unable to find adapter in interface table")?)
+}
+
+/// How many names [`Adapter::create_unique`] tries before giving up
+const MAX_UNIQUE_NAME_ATTEMPTS: u32 = 100;
+
+fn is_already_exists(err: &Error) -> bool {
+    let code = err.raw_os_error().map(|code| code as u32);
+    code == Some(ERROR_ALREADY_EXISTS) || code == Some(ERROR_OBJECT_ALREADY_EXISTS)
+}
+
+fn is_not_found(err: &Error) -> bool {
+    let code = err.raw_os_error().map(|code| code as u32);
+    code == Some(ERROR_NOT_FOUND) || code == Some(ERROR_INVALID_PARAMETER)
+}
+
+/// The minimum MTU of an IPv6 link, used as the floor by [`Adapter::set_mtu_from_underlay`]
+const MIN_TUNNEL_MTU: u32 = 1280;
+
+/// Returns the MTU of the interface with `luid` as seen by IPv4, or by IPv6 if IPv4 is not enabled
+fn interface_mtu(luid: &NET_LUID_LH) -> Result<u32, Error> {
+    match crate::ffi::get_ip_interface_mtu(luid, AF_INET) {
+        Err(err) if err.raw_os_error() == Some(ERROR_NOT_FOUND as i32) => {
+            Ok(crate::ffi::get_ip_interface_mtu(luid, AF_INET6)?)
+        }
+        mtu => Ok(mtu?),
+    }
+}
+
+/// Checks that `capacity` is a valid ring capacity for [`Adapter::start_session`]
+pub(crate) fn check_ring_capacity(capacity: u32) -> Result<(), Error> {
+    let range = crate::MIN_RING_CAPACITY..=crate::MAX_RING_CAPACITY;
+    if !range.contains(&capacity) {
+        return Err(Error::CapacityOutOfRange(OutOfRangeData { range, value: capacity }));
+    }
+    if !capacity.is_power_of_two() {
+        return Err(Error::CapacityNotPowerOfTwo(capacity));
+    }
+    Ok(())
+}
+
 impl Drop for Adapter {
     fn drop(&mut self) {
         //Close adapter on drop
         //This is why we need an Arc of wintun
-        unsafe { self.wintun.WintunCloseAdapter(self.adapter.0) };
+        //No session can be alive here because each one holds an Arc of its adapter
+        unsafe { self.wintun.raw().WintunCloseAdapter(self.adapter.0) };
         self.adapter = UnsafeHandle(ptr::null_mut());
     }
 }
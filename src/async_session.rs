@@ -0,0 +1,170 @@
+#[cfg(not(feature = "logging"))]
+use crate::log::noop as log;
+use crate::{packet::RecvPacket, session::Session, util, Error, Win32Error};
+use futures_core::Stream;
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll},
+};
+use tokio::task::JoinHandle;
+use windows_sys::Win32::{
+    Foundation::FALSE,
+    System::Threading::{SetEvent, INFINITE},
+};
+
+/// Async wrapper around a [`Session`] for use with the tokio runtime, enabled by the `tokio`
+/// feature
+///
+/// Waiting for the read event is done on tokio's blocking thread pool, so awaiting a packet never
+/// blocks the async executor. Calling [`AsyncSession::shutdown`] makes all pending calls to
+/// [`AsyncSession::recv`] return [`Error::ShuttingDown`]
+#[derive(Clone)]
+pub struct AsyncSession {
+    session: Arc<Session>,
+}
+
+impl AsyncSession {
+    pub fn new(session: Arc<Session>) -> Self {
+        Self { session }
+    }
+
+    /// Returns the session this wrapper receives from, for sending packets or blocking calls
+    pub fn session(&self) -> &Arc<Session> {
+        &self.session
+    }
+
+    /// Waits until a packet is available and returns it
+    ///
+    /// Cancelling the returned future, for example with `tokio::select!` or `tokio::time::timeout`,
+    /// also ends the wait on the blocking thread pool, so no thread or session reference is leaked
+    pub async fn recv(&self) -> Result<RecvPacket, Error> {
+        loop {
+            if let Some(packet) = self.session.try_receive()? {
+                return Ok(packet);
+            }
+            ReadWait::spawn(&self.session)?.await?;
+        }
+    }
+
+    /// See [`Session::shutdown`]
+    pub fn shutdown(&self) -> Result<bool, Error> {
+        self.session.shutdown()
+    }
+
+    /// Turns this wrapper into a [`Stream`] of received packets, see [`PacketStream`]
+    pub fn into_stream(self) -> PacketStream {
+        PacketStream {
+            session: self.session,
+            wait: None,
+            done: false,
+        }
+    }
+}
+
+/// A [`Stream`] of the packets received on a session, created by [`AsyncSession::into_stream`]
+///
+/// Packets are copied into owned `Vec<u8>`s so they can be held across `.await` points and passed
+/// through stream combinators without keeping slots of the receive ring borrowed. Waiting for
+/// packets happens on tokio's blocking thread pool like [`AsyncSession::recv`], and dropping the
+/// stream cancels a pending wait. The stream ends once the session is shut down. After an error it
+/// only keeps going if the error is transient, otherwise the error is its last item
+pub struct PacketStream {
+    session: Arc<Session>,
+    wait: Option<ReadWait>,
+    done: bool,
+}
+
+impl PacketStream {
+    fn fail(&mut self, err: Error) -> Poll<Option<Result<Vec<u8>, Error>>> {
+        self.done = !err.is_transient();
+        Poll::Ready(Some(Err(err)))
+    }
+}
+
+impl Stream for PacketStream {
+    type Item = Result<Vec<u8>, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        while !this.done {
+            if let Some(wait) = this.wait.as_mut() {
+                let result = ready!(Pin::new(wait).poll(cx));
+                this.wait = None;
+                match result {
+                    Ok(_) => {}
+                    Err(Error::ShuttingDown) => this.done = true,
+                    Err(err) => return this.fail(err),
+                }
+                continue;
+            }
+            if this.session.is_shutdown() {
+                this.done = true;
+                break;
+            }
+            match this.session.try_receive() {
+                Ok(Some(packet)) => return Poll::Ready(Some(Ok(packet.into()))),
+                Ok(None) => {}
+                Err(err) => return this.fail(err),
+            }
+            match ReadWait::spawn(&this.session) {
+                Ok(wait) => this.wait = Some(wait),
+                Err(err) => return this.fail(err),
+            }
+        }
+        Poll::Ready(None)
+    }
+}
+
+/// A wait for the read event on tokio's blocking thread pool, which is cancelled when dropped
+///
+/// Resolves to Ok(true) once data may be available and to [`Error::ShuttingDown`] after a shutdown.
+/// Dropping it signals a private cancel event, so the blocking thread returns right away and drops
+/// its reference to the session instead of waiting for the next packet
+struct ReadWait {
+    task: JoinHandle<Result<bool, Error>>,
+    cancel: Arc<util::OwnedEvent>,
+}
+
+impl ReadWait {
+    fn spawn(session: &Arc<Session>) -> Result<Self, Error> {
+        let cancel = Arc::new(util::OwnedEvent::new_manual_reset()?);
+        let session = session.clone();
+        let task_cancel = cancel.clone();
+        let task = tokio::task::spawn_blocking(move || {
+            session.wait_for_read_event_or_cancel(INFINITE, Some(task_cancel.raw()))
+        });
+        Ok(Self { task, cancel })
+    }
+}
+
+impl Future for ReadWait {
+    type Output = Result<bool, Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let result = ready!(Pin::new(&mut self.task).poll(cx));
+        Poll::Ready(result.map_err(|err| Error::from(format!("Receive task failed: {}", err)))?)
+    }
+}
+
+impl Drop for ReadWait {
+    fn drop(&mut self) {
+        //Harmless if the wait already finished. The event is kept open by the task until it returns
+        if FALSE == unsafe { SetEvent(self.cancel.raw()) } {
+            log::warn!("Failed to cancel a pending receive: {}", Win32Error::last());
+        }
+    }
+}
+
+impl From<Session> for AsyncSession {
+    fn from(session: Session) -> Self {
+        Self::new(Arc::new(session))
+    }
+}
+
+impl From<Arc<Session>> for AsyncSession {
+    fn from(session: Arc<Session>) -> Self {
+        Self::new(session)
+    }
+}
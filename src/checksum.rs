@@ -0,0 +1,244 @@
+use crate::Error;
+
+const PROTO_ICMP: u8 = 1;
+const PROTO_TCP: u8 = 6;
+const PROTO_UDP: u8 = 17;
+const PROTO_ICMPV6: u8 = 58;
+
+//IPv6 extension headers that are skipped to find the transport header
+const EXT_HOP_BY_HOP: u8 = 0;
+const EXT_ROUTING: u8 = 43;
+const EXT_FRAGMENT: u8 = 44;
+const EXT_DESTINATION: u8 = 60;
+
+/// The transport header of a packet whose checksum is recomputed
+struct Transport {
+    start: usize,
+    end: usize,
+    checksum_at: usize,
+    //Sum of the pseudo header, or 0 for ICMP which has none
+    pseudo_sum: u32,
+    is_udp: bool,
+}
+
+/// The checksums found in an IP packet by [`Checksums::parse`]
+///
+/// Parsing is separate from [`Checksums::apply`] so that malformed packets are rejected before any
+/// space is allocated in the send ring
+pub(crate) struct Checksums {
+    ipv4_header_len: Option<usize>,
+    transport: Option<Transport>,
+}
+
+impl Checksums {
+    /// Validates the IP header of `packet` and locates its checksums.
+    ///
+    /// Only TCP, UDP, ICMP and ICMPv6 transport checksums are recomputed. Fragments and other
+    /// protocols only have their IPv4 header checksum recomputed, since their transport checksum
+    /// either spans several packets or is unknown to us
+    pub(crate) fn parse(packet: &[u8]) -> Result<Self, Error> {
+        match packet.first().map(|b| b >> 4) {
+            Some(4) => Self::parse_ipv4(packet),
+            Some(6) => Self::parse_ipv6(packet),
+            Some(version) => Err(Error::MalformedPacket(format!("Unknown IP version {}", version))),
+            None => Err(Error::MalformedPacket("Empty packet".to_string())),
+        }
+    }
+
+    fn parse_ipv4(packet: &[u8]) -> Result<Self, Error> {
+        if packet.len() < 20 {
+            return Err(malformed("IPv4 header", 20, packet.len()));
+        }
+        let header_len = ((packet[0] & 0x0f) as usize) * 4;
+        if header_len < 20 || header_len > packet.len() {
+            let msg = format!("Invalid IPv4 header length {}", header_len);
+            return Err(Error::MalformedPacket(msg));
+        }
+        let total_len = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+        if total_len < header_len || total_len > packet.len() {
+            return Err(malformed("IPv4 total length", total_len, packet.len()));
+        }
+
+        //The more fragments flag or a non zero offset mark a fragment
+        let is_fragment = u16::from_be_bytes([packet[6], packet[7]]) & 0x3fff != 0;
+        let protocol = packet[9];
+        let transport = if is_fragment {
+            None
+        } else {
+            let mut pseudo = [0u8; 12];
+            pseudo[..8].copy_from_slice(&packet[12..20]);
+            pseudo[9] = protocol;
+            pseudo[10..].copy_from_slice(&((total_len - header_len) as u16).to_be_bytes());
+            let pseudo_sum = if protocol == PROTO_ICMP { 0 } else { sum(&pseudo, 0) };
+            locate_transport(packet, protocol, header_len, total_len, pseudo_sum)?
+        };
+        Ok(Self {
+            ipv4_header_len: Some(header_len),
+            transport,
+        })
+    }
+
+    fn parse_ipv6(packet: &[u8]) -> Result<Self, Error> {
+        if packet.len() < 40 {
+            return Err(malformed("IPv6 header", 40, packet.len()));
+        }
+        let total_len = 40 + u16::from_be_bytes([packet[4], packet[5]]) as usize;
+        if total_len > packet.len() {
+            return Err(malformed("IPv6 payload length", total_len, packet.len()));
+        }
+
+        let mut next_header = packet[6];
+        let mut offset = 40;
+        loop {
+            match next_header {
+                EXT_HOP_BY_HOP | EXT_ROUTING | EXT_DESTINATION => {
+                    if offset + 8 > total_len {
+                        return Err(malformed("IPv6 extension header", offset + 8, total_len));
+                    }
+                    next_header = packet[offset];
+                    offset += (packet[offset + 1] as usize + 1) * 8;
+                }
+                EXT_FRAGMENT => {
+                    return Ok(Self {
+                        ipv4_header_len: None,
+                        transport: None,
+                    })
+                }
+                _ => break,
+            }
+        }
+        if offset > total_len {
+            return Err(malformed("IPv6 extension header", offset, total_len));
+        }
+
+        //Uses the destination from the fixed header, so packets with a routing header that
+        //changes the final destination get the wrong transport checksum
+        let mut pseudo = [0u8; 40];
+        pseudo[..32].copy_from_slice(&packet[8..40]);
+        pseudo[32..36].copy_from_slice(&((total_len - offset) as u32).to_be_bytes());
+        pseudo[39] = next_header;
+        let pseudo_sum = sum(&pseudo, 0);
+        Ok(Self {
+            ipv4_header_len: None,
+            transport: locate_transport(packet, next_header, offset, total_len, pseudo_sum)?,
+        })
+    }
+
+    /// Writes the checksums into `packet`, which must hold the same headers that were passed to
+    /// [`Checksums::parse`]
+    pub(crate) fn apply(&self, packet: &mut [u8]) {
+        if let Some(header_len) = self.ipv4_header_len {
+            packet[10..12].fill(0);
+            let checksum = fold(sum(&packet[..header_len], 0));
+            packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+        }
+        if let Some(transport) = &self.transport {
+            let at = transport.checksum_at;
+            packet[at..at + 2].fill(0);
+            let mut checksum = fold(sum(&packet[transport.start..transport.end], transport.pseudo_sum));
+            //A zero UDP checksum means no checksum, so a computed zero is sent as all ones
+            if transport.is_udp && checksum == 0 {
+                checksum = 0xffff;
+            }
+            packet[at..at + 2].copy_from_slice(&checksum.to_be_bytes());
+        }
+    }
+}
+
+fn locate_transport(
+    packet: &[u8],
+    protocol: u8,
+    start: usize,
+    end: usize,
+    pseudo_sum: u32,
+) -> Result<Option<Transport>, Error> {
+    let (name, min_len, checksum_offset) = match protocol {
+        PROTO_TCP => ("TCP header", 20, 16),
+        PROTO_UDP => ("UDP header", 8, 6),
+        PROTO_ICMP | PROTO_ICMPV6 => ("ICMP header", 4, 2),
+        _ => return Ok(None),
+    };
+    if end - start < min_len {
+        return Err(malformed(name, min_len, end - start));
+    }
+    if protocol == PROTO_TCP {
+        let data_offset = ((packet[start + 12] >> 4) as usize) * 4;
+        if data_offset < 20 || data_offset > end - start {
+            let msg = format!("Invalid TCP data offset {}", data_offset);
+            return Err(Error::MalformedPacket(msg));
+        }
+    }
+    Ok(Some(Transport {
+        start,
+        end,
+        checksum_at: start + checksum_offset,
+        pseudo_sum,
+        is_udp: protocol == PROTO_UDP,
+    }))
+}
+
+fn malformed(what: &str, needed: usize, available: usize) -> Error {
+    let msg = format!("{} needs {} bytes, but only {} are available", what, needed, available);
+    Error::MalformedPacket(msg)
+}
+
+/// Adds up `data` as big endian 16 bit words on top of `initial`, padding an odd trailing byte
+/// with zero. Packets are at most 64KiB, so the sum cannot overflow
+fn sum(data: &[u8], initial: u32) -> u32 {
+    let mut chunks = data.chunks_exact(2);
+    let mut sum = initial;
+    for chunk in &mut chunks {
+        sum += u16::from_be_bytes([chunk[0], chunk[1]]) as u32;
+    }
+    if let [last] = chunks.remainder() {
+        sum += (*last as u32) << 8;
+    }
+    sum
+}
+
+/// Folds the carries of `sum` back in and returns its ones' complement
+fn fold(mut sum: u32) -> u16 {
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[test]
+fn test_ipv4_checksums() {
+    #[rustfmt::skip]
+    let mut packet = [
+        0x45, 0x00, 0x00, 0x21, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00,
+        0xc0, 0xa8, 0x00, 0x01, 0xc0, 0xa8, 0x00, 0xc7,
+        //UDP header and a 5 byte payload
+        0x30, 0x39, 0x00, 0x35, 0x00, 0x0d, 0x00, 0x00, b'h', b'e', b'l', b'l', b'o',
+    ];
+    Checksums::parse(&packet).unwrap().apply(&mut packet);
+    //Summing a packet over its correct checksum yields zero
+    assert_eq!(fold(sum(&packet[..20], 0)), 0);
+    assert_eq!(&packet[10..12], [0xb8, 0xb3]);
+    let pseudo = [0xc0, 0xa8, 0x00, 0x01, 0xc0, 0xa8, 0x00, 0xc7, 0, 17, 0, 13];
+    assert_eq!(fold(sum(&packet[20..], sum(&pseudo, 0))), 0);
+
+    //A total length that runs past the end of the buffer
+    packet[3] = 0x40;
+    assert!(matches!(Checksums::parse(&packet), Err(Error::MalformedPacket(_))));
+}
+
+#[test]
+fn test_ipv6_checksums() {
+    let mut packet = vec![0u8; 40 + 8];
+    packet[0] = 0x60;
+    packet[5] = 8;
+    packet[6] = PROTO_ICMPV6;
+    packet[23] = 1;
+    packet[39] = 2;
+    packet[40] = 128;
+    Checksums::parse(&packet).unwrap().apply(&mut packet);
+    let mut pseudo = packet[8..40].to_vec();
+    pseudo.extend_from_slice(&[0, 0, 0, 8, 0, 0, 0, PROTO_ICMPV6]);
+    assert_eq!(fold(sum(&packet[40..], sum(&pseudo, 0))), 0);
+
+    packet.truncate(42);
+    assert!(Checksums::parse(&packet).is_err());
+}
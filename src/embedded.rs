@@ -0,0 +1,84 @@
+use crate::{Error, Wintun};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+/// The signed wintun.dll for the architecture we are compiled for
+#[cfg(target_arch = "x86")]
+const WINTUN_DLL: &[u8] = include_bytes!("../wintun/bin/x86/wintun.dll");
+#[cfg(target_arch = "x86_64")]
+const WINTUN_DLL: &[u8] = include_bytes!("../wintun/bin/amd64/wintun.dll");
+#[cfg(target_arch = "arm")]
+const WINTUN_DLL: &[u8] = include_bytes!("../wintun/bin/arm/wintun.dll");
+#[cfg(target_arch = "aarch64")]
+const WINTUN_DLL: &[u8] = include_bytes!("../wintun/bin/arm64/wintun.dll");
+#[cfg(not(any(
+    target_arch = "x86",
+    target_arch = "x86_64",
+    target_arch = "arm",
+    target_arch = "aarch64"
+)))]
+compile_error!("The embedded-dll feature is not supported on this architecture");
+
+static EMBEDDED: OnceLock<Wintun> = OnceLock::new();
+
+/// Returns true if the file at `path` holds exactly the embedded dll
+fn is_extracted(path: &Path) -> io::Result<bool> {
+    match fs::metadata(path) {
+        Ok(meta) if meta.len() != WINTUN_DLL.len() as u64 => Ok(false),
+        Ok(_) => Ok(fs::read(path)? == WINTUN_DLL),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+/// Writes the embedded dll to the temp directory if it is not already there, returning its path
+fn extract() -> io::Result<PathBuf> {
+    let dir = std::env::temp_dir().join(format!("wintun-rs-{}", env!("CARGO_PKG_VERSION")));
+    let path = dir.join("wintun.dll");
+    if is_extracted(&path)? {
+        return Ok(path);
+    }
+
+    fs::create_dir_all(&dir)?;
+    //Write to a process specific file first so that a concurrent load never sees a partial dll
+    let staging = dir.join(format!("wintun.dll.{}.tmp", std::process::id()));
+    fs::write(&staging, WINTUN_DLL)?;
+    if let Err(err) = fs::rename(&staging, &path) {
+        let _ = fs::remove_file(&staging);
+        //Another process may have extracted and loaded the dll in the meantime
+        if !is_extracted(&path)? {
+            return Err(err);
+        }
+    }
+
+    //Guard against the file being swapped out between writing and loading it
+    if !is_extracted(&path)? {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Extracted wintun.dll does not match",
+        ));
+    }
+    Ok(path)
+}
+
+/// Loads the signed wintun.dll that is embedded in this crate by the `embedded-dll` feature.
+///
+/// On first use the dll is written to a `wintun-rs-<version>` folder inside the temp directory
+/// and verified against the embedded copy before being loaded. Later calls return the same
+/// library without touching the file system again.
+///
+/// # Safety
+/// The extracted file is checked to match the embedded dll byte for byte, but like [`crate::load`]
+/// this still runs the DllMain of whatever file is found at that path when it is loaded.
+/// See [`crate::load_from_path`] for details
+pub unsafe fn load_embedded() -> Result<Wintun, Error> {
+    if let Some(wintun) = EMBEDDED.get() {
+        return Ok(wintun.clone());
+    }
+    let path = extract()?;
+    let wintun = unsafe { crate::load_from_path(path)? };
+    Ok(EMBEDDED.get_or_init(|| wintun).clone())
+}
@@ -5,11 +5,62 @@ pub struct OutOfRangeData<T> {
     pub value: T,
 }
 
+/// A Win32 error code, as set by a failed system or driver call and read with `GetLastError`
+///
+/// `Display` shows the message Windows has for the code, decoded with `FormatMessageW`, followed by
+/// the code itself. Converts into [`Error::Io`], so [`Error::raw_os_error`] keeps working
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Win32Error(u32);
+
+impl Win32Error {
+    pub fn new(code: u32) -> Self {
+        Self(code)
+    }
+
+    /// Returns the last error of the calling thread. Must be called right after the failed call,
+    /// before anything else can overwrite the last error
+    pub fn last() -> Self {
+        Self(unsafe { windows_sys::Win32::Foundation::GetLastError() })
+    }
+
+    /// Returns the raw Win32 error code
+    pub fn code(&self) -> u32 {
+        self.0
+    }
+}
+
+impl std::fmt::Display for Win32Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match crate::util::format_message(self.0) {
+            Ok(message) => write!(f, "{} (error {})", message.trim_end(), self.0),
+            Err(_) => write!(f, "Win32 error {}", self.0),
+        }
+    }
+}
+
+impl std::error::Error for Win32Error {}
+
+impl From<Win32Error> for std::io::Error {
+    fn from(value: Win32Error) -> Self {
+        std::io::Error::from_raw_os_error(value.0 as i32)
+    }
+}
+
+impl From<Win32Error> for Error {
+    fn from(value: Win32Error) -> Self {
+        Error::Io(value.into())
+    }
+}
+
 /// Error type returned when preconditions of this API are broken
+///
+/// Implements [`std::error::Error`], so it converts into `Box<dyn Error>` or `anyhow::Error` with
+/// `?`. Failed system and driver calls display the decoded Win32 message followed by the error
+/// code, and return the underlying [`std::io::Error`] from `source`
 #[derive(thiserror::Error, Debug)]
 #[non_exhaustive]
 pub enum Error {
-    #[error(transparent)]
+    #[error("{0}")]
     Io(#[from] std::io::Error),
 
     #[error("CapacityNotPowerOfTwo {0}")]
@@ -47,6 +98,66 @@ pub enum Error {
 
     #[error("Session shutting down")]
     ShuttingDown,
+
+    /// The send ring has no space for the packet right now, retrying later can succeed
+    #[error("Send ring is full")]
+    RingFull,
+
+    #[error("{waiters} readers did not wake up after the session was shut down")]
+    ShutdownTimeout { waiters: usize },
+
+    #[error("A session is already active on this adapter")]
+    SessionAlreadyActive,
+
+    #[error("Buffer too small, the packet needs {needed} bytes")]
+    BufferTooSmall { needed: usize },
+
+    #[error("wintun.dll does not export {0}, it is likely the wrong version")]
+    MissingSymbol(&'static str),
+
+    #[error("Invalid adapter name: {0}")]
+    InvalidName(String),
+
+    #[error("Wintun returned a received packet of {0} bytes, more than an IP packet can hold")]
+    PacketTooLarge(u32),
+
+    #[error("Malformed packet: {0}")]
+    MalformedPacket(String),
+
+    #[error("No interface with the alias {0:?} exists")]
+    NotFound(String),
+
+    #[error("An interface named {0:?} already exists")]
+    NameInUse(String),
+
+    #[error("Wintun driver version {found} is older than the required version {required}")]
+    DriverTooOld {
+        found: crate::Version,
+        required: crate::Version,
+    },
+}
+
+impl Error {
+    /// Returns the Win32 error code carried by this error, if it originated from a failed system
+    /// or driver call. The human readable message is available through the `Display` impl
+    pub fn raw_os_error(&self) -> Option<i32> {
+        match self {
+            Error::Io(io) => io.raw_os_error(),
+            _ => None,
+        }
+    }
+
+    /// Returns the Win32 error carried by this error, see [`Error::raw_os_error`]
+    pub fn win32_error(&self) -> Option<Win32Error> {
+        self.raw_os_error().map(|code| Win32Error::new(code as u32))
+    }
+
+    /// Returns true if this is a driver error that is known to happen intermittently under heavy
+    /// load, such as `ERROR_GEN_FAILURE` from WintunReceivePacket, so the call can be retried
+    pub fn is_transient(&self) -> bool {
+        use windows_sys::Win32::Foundation::ERROR_GEN_FAILURE;
+        self.raw_os_error() == Some(ERROR_GEN_FAILURE as i32)
+    }
 }
 
 impl From<String> for Error {
@@ -77,9 +188,26 @@ impl From<Error> for std::io::Error {
     fn from(value: Error) -> Self {
         match value {
             Error::Io(io) => io,
+            Error::RingFull => std::io::Error::new(std::io::ErrorKind::WouldBlock, value),
             _ => std::io::Error::new(std::io::ErrorKind::Other, value),
         }
     }
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[test]
+fn test_ring_full_is_would_block() {
+    let err = std::io::Error::from(Error::RingFull);
+    assert_eq!(err.kind(), std::io::ErrorKind::WouldBlock);
+    assert!(!Error::RingFull.is_transient());
+}
+
+#[test]
+fn test_win32_error_round_trip() {
+    use windows_sys::Win32::Foundation::ERROR_GEN_FAILURE;
+    let err = Error::from(Win32Error::new(ERROR_GEN_FAILURE));
+    assert!(err.is_transient());
+    assert_eq!(err.win32_error().map(|err| err.code()), Some(ERROR_GEN_FAILURE));
+    assert_eq!(Error::RingFull.win32_error(), None);
+}
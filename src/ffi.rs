@@ -1,10 +1,20 @@
 use crate::wintun_raw::WCHAR;
-use std::{io, mem};
+use std::{io, mem, net::IpAddr};
 use windows_sys::core::GUID;
+use windows_sys::Win32::Foundation::{ERROR_NOT_FOUND, ERROR_OBJECT_ALREADY_EXISTS, NO_ERROR};
 use windows_sys::Win32::NetworkManagement::IpHelper::{
     ConvertInterfaceAliasToLuid, ConvertInterfaceLuidToAlias, ConvertInterfaceLuidToGuid, ConvertInterfaceLuidToIndex,
+    CreateIpForwardEntry2, CreateUnicastIpAddressEntry, DeleteIpForwardEntry2, DeleteUnicastIpAddressEntry,
+    FreeMibTable, GetIfEntry, GetIfEntry2, GetIpForwardTable2, GetIpInterfaceEntry, GetUnicastIpAddressEntry,
+    GetUnicastIpAddressTable, InitializeIpForwardEntry, InitializeIpInterfaceEntry, InitializeUnicastIpAddressEntry,
+    SetIfEntry, SetIpInterfaceEntry, MIB_IFROW, MIB_IF_ADMIN_STATUS_DOWN, MIB_IF_ADMIN_STATUS_UP, MIB_IF_ROW2,
+    MIB_IPFORWARD_ROW2, MIB_IPFORWARD_TABLE2, MIB_IPINTERFACE_ROW, MIB_UNICASTIPADDRESS_ROW,
+    MIB_UNICASTIPADDRESS_TABLE,
 };
 use windows_sys::Win32::NetworkManagement::Ndis::NET_LUID_LH;
+use windows_sys::Win32::Networking::WinSock::{
+    IpPrefixOriginManual, ADDRESS_FAMILY, AF_INET, AF_INET6, AF_UNSPEC, MIB_IPPROTO_NETMGMT, SOCKADDR_INET,
+};
 
 pub fn luid_to_alias(luid: &NET_LUID_LH) -> io::Result<Vec<WCHAR>> {
     // IF_MAX_STRING_SIZE + 1
@@ -41,3 +51,262 @@ pub fn luid_to_guid(luid: &NET_LUID_LH) -> io::Result<GUID> {
         err => Err(io::Error::from_raw_os_error(err as _)),
     }
 }
+
+fn unicast_ip_address_row(luid: &NET_LUID_LH, address: SOCKADDR_INET, prefix_len: u8) -> MIB_UNICASTIPADDRESS_ROW {
+    let mut row: MIB_UNICASTIPADDRESS_ROW = unsafe { mem::zeroed() };
+    unsafe { InitializeUnicastIpAddressEntry(&mut row) };
+    row.InterfaceLuid = *luid;
+    row.Address = address;
+    row.OnLinkPrefixLength = prefix_len;
+    row
+}
+
+/// Adds `address` to the interface `luid`. Fails with ERROR_OBJECT_ALREADY_EXISTS if the address
+/// is already assigned
+pub fn add_unicast_ip_address(luid: &NET_LUID_LH, address: SOCKADDR_INET, prefix_len: u8) -> io::Result<()> {
+    let row = unicast_ip_address_row(luid, address, prefix_len);
+
+    match unsafe { CreateUnicastIpAddressEntry(&row) } {
+        0 => Ok(()),
+        err => Err(io::Error::from_raw_os_error(err as _)),
+    }
+}
+
+/// Adds `address` to the interface `luid`. Succeeds if the address is already assigned with the
+/// same prefix length
+pub fn create_unicast_ip_address(luid: &NET_LUID_LH, address: SOCKADDR_INET, prefix_len: u8) -> io::Result<()> {
+    let row = unicast_ip_address_row(luid, address, prefix_len);
+
+    match unsafe { CreateUnicastIpAddressEntry(&row) } {
+        0 => Ok(()),
+        ERROR_OBJECT_ALREADY_EXISTS => {
+            let mut existing = row;
+            match unsafe { GetUnicastIpAddressEntry(&mut existing) } {
+                0 if existing.OnLinkPrefixLength == prefix_len => Ok(()),
+                0 => Err(io::Error::from_raw_os_error(ERROR_OBJECT_ALREADY_EXISTS as _)),
+                err => Err(io::Error::from_raw_os_error(err as _)),
+            }
+        }
+        err => Err(io::Error::from_raw_os_error(err as _)),
+    }
+}
+
+pub fn delete_unicast_ip_address(luid: &NET_LUID_LH, address: SOCKADDR_INET) -> io::Result<()> {
+    let row = unicast_ip_address_row(luid, address, 0);
+
+    match unsafe { DeleteUnicastIpAddressEntry(&row) } {
+        0 => Ok(()),
+        err => Err(io::Error::from_raw_os_error(err as _)),
+    }
+}
+
+/// Returns the rows of the unicast address table that belong to the interface with `luid`, using
+/// GetUnicastIpAddressTable
+fn get_unicast_ip_address_rows(luid: &NET_LUID_LH) -> io::Result<Vec<MIB_UNICASTIPADDRESS_ROW>> {
+    let mut table: *mut MIB_UNICASTIPADDRESS_TABLE = std::ptr::null_mut();
+    match unsafe { GetUnicastIpAddressTable(AF_UNSPEC, &mut table) } {
+        NO_ERROR => {}
+        err => return Err(io::Error::from_raw_os_error(err as _)),
+    }
+    let rows = unsafe {
+        let table_ptr = &(*table).Table as *const MIB_UNICASTIPADDRESS_ROW;
+        std::slice::from_raw_parts(table_ptr, (*table).NumEntries as usize)
+    };
+    let rows = rows
+        .iter()
+        .filter(|row| unsafe { row.InterfaceLuid.Value == luid.Value })
+        .copied()
+        .collect();
+    unsafe { FreeMibTable(table as *const _) };
+    Ok(rows)
+}
+
+/// Returns the rows of the IP forward table that belong to the interface with `luid`, using
+/// GetIpForwardTable2
+fn get_ip_forward_rows(luid: &NET_LUID_LH) -> io::Result<Vec<MIB_IPFORWARD_ROW2>> {
+    let mut table: *mut MIB_IPFORWARD_TABLE2 = std::ptr::null_mut();
+    match unsafe { GetIpForwardTable2(AF_UNSPEC, &mut table) } {
+        NO_ERROR => {}
+        err => return Err(io::Error::from_raw_os_error(err as _)),
+    }
+    let rows = unsafe {
+        let table_ptr = &(*table).Table as *const MIB_IPFORWARD_ROW2;
+        std::slice::from_raw_parts(table_ptr, (*table).NumEntries as usize)
+    };
+    let rows = rows
+        .iter()
+        .filter(|row| unsafe { row.InterfaceLuid.Value == luid.Value })
+        .copied()
+        .collect();
+    unsafe { FreeMibTable(table as *const _) };
+    Ok(rows)
+}
+
+/// Returns the unicast addresses and their prefix lengths of the interface with `luid`, using
+/// GetUnicastIpAddressTable
+pub fn get_unicast_ip_addresses(luid: &NET_LUID_LH) -> io::Result<Vec<(IpAddr, u8)>> {
+    let mut addresses = Vec::new();
+    for row in get_unicast_ip_address_rows(luid)? {
+        let address = unsafe {
+            match row.Address.si_family {
+                AF_INET => crate::util::sockaddr_in_to_socket_addr(&row.Address.Ipv4).ip(),
+                AF_INET6 => crate::util::sockaddr_in6_to_socket_addr(&row.Address.Ipv6).ip(),
+                _ => continue,
+            }
+        };
+        addresses.push((address, row.OnLinkPrefixLength));
+    }
+    Ok(addresses)
+}
+
+/// Deletes the manually configured unicast addresses and static routes of the interface with
+/// `luid`. Keeps going after a failed deletion and returns the first error afterwards
+pub fn purge_ip_config(luid: &NET_LUID_LH) -> io::Result<()> {
+    let mut first_err = None;
+    let mut record = |err: u32| {
+        //Deleting an address also deletes the routes through it, so they may be gone already
+        if err != NO_ERROR && err != ERROR_NOT_FOUND && first_err.is_none() {
+            first_err = Some(io::Error::from_raw_os_error(err as _));
+        }
+    };
+    for row in get_ip_forward_rows(luid)? {
+        if row.Protocol == MIB_IPPROTO_NETMGMT {
+            record(unsafe { DeleteIpForwardEntry2(&row) });
+        }
+    }
+    for row in get_unicast_ip_address_rows(luid)? {
+        if row.PrefixOrigin == IpPrefixOriginManual {
+            record(unsafe { DeleteUnicastIpAddressEntry(&row) });
+        }
+    }
+    first_err.map_or(Ok(()), Err)
+}
+
+fn get_ip_interface_row(luid: &NET_LUID_LH, family: ADDRESS_FAMILY) -> io::Result<MIB_IPINTERFACE_ROW> {
+    let mut row: MIB_IPINTERFACE_ROW = unsafe { mem::zeroed() };
+    unsafe { InitializeIpInterfaceEntry(&mut row) };
+    row.Family = family;
+    row.InterfaceLuid = *luid;
+
+    match unsafe { GetIpInterfaceEntry(&mut row) } {
+        NO_ERROR => Ok(row),
+        err => Err(io::Error::from_raw_os_error(err as _)),
+    }
+}
+
+pub fn get_ip_interface_mtu(luid: &NET_LUID_LH, family: ADDRESS_FAMILY) -> io::Result<u32> {
+    Ok(get_ip_interface_row(luid, family)?.NlMtu)
+}
+
+/// Reads the IP interface row of `luid` for `family`, lets `update` change it and writes it back
+/// with SetIpInterfaceEntry
+fn update_ip_interface_row<F>(luid: &NET_LUID_LH, family: ADDRESS_FAMILY, update: F) -> io::Result<()>
+where
+    F: FnOnce(&mut MIB_IPINTERFACE_ROW),
+{
+    let mut row = get_ip_interface_row(luid, family)?;
+    if family == AF_INET {
+        //SetIpInterfaceEntry rejects IPv4 rows unless this is zero
+        row.SitePrefixLength = 0;
+    }
+    update(&mut row);
+
+    match unsafe { SetIpInterfaceEntry(&mut row) } {
+        NO_ERROR => Ok(()),
+        err => Err(io::Error::from_raw_os_error(err as _)),
+    }
+}
+
+pub fn set_ip_interface_mtu(luid: &NET_LUID_LH, family: ADDRESS_FAMILY, mtu: u32) -> io::Result<()> {
+    update_ip_interface_row(luid, family, |row| row.NlMtu = mtu)
+}
+
+pub fn set_ip_interface_metric(luid: &NET_LUID_LH, family: ADDRESS_FAMILY, metric: u32) -> io::Result<()> {
+    update_ip_interface_row(luid, family, |row| {
+        row.UseAutomaticMetric = 0;
+        row.Metric = metric;
+    })
+}
+
+pub fn set_ip_interface_forwarding(luid: &NET_LUID_LH, family: ADDRESS_FAMILY, enabled: bool) -> io::Result<()> {
+    update_ip_interface_row(luid, family, |row| row.ForwardingEnabled = enabled.into())
+}
+
+/// Returns the interface table row of the interface with `luid`, using GetIfEntry2
+pub fn get_if_entry(luid: &NET_LUID_LH) -> io::Result<MIB_IF_ROW2> {
+    let mut row: MIB_IF_ROW2 = unsafe { mem::zeroed() };
+    row.InterfaceLuid = *luid;
+    match unsafe { GetIfEntry2(&mut row) } {
+        NO_ERROR => Ok(row),
+        err => Err(io::Error::from_raw_os_error(err as _)),
+    }
+}
+
+/// Sets the administrative status of the interface with index `index`, using SetIfEntry
+pub fn set_if_admin_status(index: u32, up: bool) -> io::Result<()> {
+    let mut row: MIB_IFROW = unsafe { mem::zeroed() };
+    row.dwIndex = index;
+    match unsafe { GetIfEntry(&mut row) } {
+        NO_ERROR => (),
+        err => return Err(io::Error::from_raw_os_error(err as _)),
+    }
+    //dwAdminStatus is the only field SetIfEntry changes
+    row.dwAdminStatus = if up {
+        MIB_IF_ADMIN_STATUS_UP
+    } else {
+        MIB_IF_ADMIN_STATUS_DOWN
+    };
+
+    match unsafe { SetIfEntry(&row) } {
+        NO_ERROR => Ok(()),
+        err => Err(io::Error::from_raw_os_error(err as _)),
+    }
+}
+
+fn ip_forward_row(
+    luid: &NET_LUID_LH,
+    destination: SOCKADDR_INET,
+    prefix_len: u8,
+    next_hop: SOCKADDR_INET,
+) -> MIB_IPFORWARD_ROW2 {
+    let mut row: MIB_IPFORWARD_ROW2 = unsafe { mem::zeroed() };
+    unsafe { InitializeIpForwardEntry(&mut row) };
+    row.InterfaceLuid = *luid;
+    row.DestinationPrefix.Prefix = destination;
+    row.DestinationPrefix.PrefixLength = prefix_len;
+    row.NextHop = next_hop;
+    row.Protocol = MIB_IPPROTO_NETMGMT;
+    row
+}
+
+/// Adds a route to `destination`/`prefix_len` through the interface `luid`. A `next_hop` with an
+/// unspecified address makes the route on-link
+pub fn create_ip_forward_entry(
+    luid: &NET_LUID_LH,
+    destination: SOCKADDR_INET,
+    prefix_len: u8,
+    next_hop: SOCKADDR_INET,
+    metric: u32,
+) -> io::Result<()> {
+    let mut row = ip_forward_row(luid, destination, prefix_len, next_hop);
+    row.Metric = metric;
+
+    match unsafe { CreateIpForwardEntry2(&row) } {
+        NO_ERROR => Ok(()),
+        err => Err(io::Error::from_raw_os_error(err as _)),
+    }
+}
+
+pub fn delete_ip_forward_entry(
+    luid: &NET_LUID_LH,
+    destination: SOCKADDR_INET,
+    prefix_len: u8,
+    next_hop: SOCKADDR_INET,
+) -> io::Result<()> {
+    let row = ip_forward_row(luid, destination, prefix_len, next_hop);
+
+    match unsafe { DeleteIpForwardEntry2(&row) } {
+        NO_ERROR => Ok(()),
+        err => Err(io::Error::from_raw_os_error(err as _)),
+    }
+}
@@ -6,10 +6,12 @@
 //! # Usage
 //!
 //! Inside your code load the wintun.dll signed driver file, downloaded from <https://wintun.net>,
-//! using [`load`], [`load_from_path`] or [`load_from_library`].
+//! using [`load`], [`load_from_path`] or [`load_from_library`], or the equivalent constructors on
+//! [`Wintun`].
 //!
-//! Then either call [`Adapter::create`] or [`Adapter::open`] to obtain a wintun
-//! adapter. Start a session with [`Adapter::start_session`].
+//! Then either call [`Adapter::create`] or [`Adapter::open`] (also available as
+//! [`Wintun::create_adapter`] and [`Wintun::open_adapter`]) to obtain a wintun adapter. Start a
+//! session with [`Adapter::start_session`].
 //!
 //! # Example
 //! ```no_run
@@ -46,7 +48,7 @@
 //! //...
 //!
 //! //Send the packet to wintun virtual adapter for processing by the system
-//! session.send_packet(packet);
+//! packet.send();
 //!
 //! //Stop any readers blocking for data on other threads
 //! //Only needed when a blocking reader is preventing shutdown Ie. it holds an Arc to the
@@ -66,19 +68,39 @@
 //! # Features
 //!
 //! - `panic_on_unsent_packets`: Panics if a send packet is dropped without being sent. Useful for
-//! debugging packet issues because unsent packets that are dropped without being sent hold up
-//! wintun's internal ring buffer.
+//!   debugging packet issues because unsent packets are otherwise silently discarded.
+//! - `embedded-dll`: Embeds the signed wintun.dll for the target architecture in the binary and
+//!   adds `load_embedded`, which extracts it to the temp directory and loads it. Saves shipping
+//!   wintun.dll alongside single binary applications.
+//! - `tokio`: Adds `AsyncSession` for awaiting received packets inside a tokio runtime, and
+//!   `AsyncSession::into_stream` for consuming them as a `futures_core::Stream`.
+//! - `metrics`: Counts how often `Session::receive_blocking` found a packet while spinning and how
+//!   often it had to wait, readable with `Session::metrics_snapshot`.
+//! - `checksum`: Adds `Session::send_ip_packet`, which can recompute the IPv4 header checksum and
+//!   the TCP, UDP or ICMP checksum of a packet before sending it. Useful after rewriting addresses
+//!   for NAT.
+//! - `logging`: Enabled by default. Forwards wintun's log messages to the `log` crate and adds the
+//!   log capture and level filtering functions. Without it `default_logger` discards messages, but
+//!   `set_logger_callback` and `set_logger` still deliver them to a custom callback.
 //!
 //! # TODO:
-//! - Add async support
-//! Requires hooking into a windows specific reactor and registering read interest on wintun's read
-//! handle. Asyncify other slow operations via tokio::spawn_blocking. As always, PR's are welcome!
+//! - Reactor based async support
+//! The `tokio` feature currently waits for the read event on tokio's blocking thread pool. Hooking
+//! into a windows specific reactor and registering read interest on wintun's read handle would
+//! avoid that. Asyncify other slow operations via tokio::spawn_blocking. As always, PR's are welcome!
 //!
 
 mod adapter;
+#[cfg(feature = "tokio")]
+mod async_session;
+#[cfg(feature = "checksum")]
+mod checksum;
+#[cfg(feature = "embedded-dll")]
+mod embedded;
 mod error;
 mod ffi;
 mod log;
+mod multi_session;
 mod packet;
 mod session;
 mod util;
@@ -94,12 +116,25 @@ mod util;
 )]
 mod wintun_raw;
 
+#[cfg(feature = "tokio")]
+pub use crate::async_session::{AsyncSession, PacketStream};
+#[cfg(feature = "embedded-dll")]
+pub use crate::embedded::load_embedded;
+#[cfg(feature = "logging")]
+pub use crate::log::{capture_last, captured, set_min_level, set_verbose_logger, verbose_logger, LogEntry};
+#[allow(deprecated)]
+pub use crate::packet::Packet;
+#[cfg(feature = "metrics")]
+pub use crate::session::MetricsSnapshot;
 pub use crate::{
-    adapter::Adapter,
-    error::{Error, OutOfRangeData, Result},
-    log::{default_logger, reset_logger, set_logger},
-    packet::Packet,
-    session::Session,
+    adapter::{
+        Adapter, AdapterBuilder, AdapterInfo, AdapterStatus, AddressFamily, InterfaceStats, IpGuard, OperStatus,
+    },
+    error::{Error, OutOfRangeData, Result, Win32Error},
+    log::{default_logger, reset_logger, set_logger, set_logger_callback, LogLevel, LoggerCallback},
+    multi_session::{MultiSession, MAX_MULTI_SESSIONS},
+    packet::{RecvPacket, SendBuffer, SendPacket},
+    session::{PacketSender, ReceiveIter, Session, SessionReader, SessionWriter, ShutdownTrigger, PACKET_ALIGNMENT},
     util::{format_message, get_active_network_interface_gateways, run_command},
 };
 pub use windows_sys::Win32::{Foundation::HANDLE, NetworkManagement::Ndis::NET_LUID_LH};
@@ -112,10 +147,109 @@ pub const MAX_RING_CAPACITY: u32 = 0x400_0000;
 /// The minimum size of wintun's internal ring buffer (in bytes)
 pub const MIN_RING_CAPACITY: u32 = 0x2_0000;
 
+/// Returns true if `capacity` can be passed to [`Adapter::start_session`], meaning it is a power of
+/// two between [`MIN_RING_CAPACITY`] and [`MAX_RING_CAPACITY`] inclusive
+///
+/// ```
+/// assert!(wintun::is_valid_ring_capacity(128 * 1024));
+/// assert!(wintun::is_valid_ring_capacity(64 * 1024 * 1024));
+/// assert!(!wintun::is_valid_ring_capacity(3 * 128 * 1024));
+/// assert!(!wintun::is_valid_ring_capacity(0));
+/// ```
+pub const fn is_valid_ring_capacity(capacity: u32) -> bool {
+    capacity >= MIN_RING_CAPACITY && capacity <= MAX_RING_CAPACITY && capacity.is_power_of_two()
+}
+
 /// Maximum pool name length including zero terminator
 pub const MAX_POOL: usize = 256;
 
-pub type Wintun = Arc<wintun_raw::wintun>;
+/// Maximum length of adapter names and tunnel types in UTF-16 code units, including the zero
+/// terminator
+pub const MAX_ADAPTER_NAME: usize = 128;
+
+/// A loaded Wintun library, obtained from [`Wintun::load`] or the free [`load`] functions.
+///
+/// Cloning is cheap and shares the same library. Every adapter and session keeps a clone, so the
+/// dll stays loaded for as long as any of them are alive.
+#[derive(Clone)]
+pub struct Wintun(Arc<wintun_raw::wintun>);
+
+impl Wintun {
+    /// Returns the raw function pointers loaded from the dll, only for use inside this crate
+    pub(crate) fn raw(&self) -> &wintun_raw::wintun {
+        &self.0
+    }
+
+    /// See [`load`]
+    ///
+    /// # Safety
+    /// See [`load`]
+    pub unsafe fn load() -> Result<Self, Error> {
+        load()
+    }
+
+    /// See [`load_from_path`]
+    ///
+    /// # Safety
+    /// See [`load_from_path`]
+    pub unsafe fn load_from_path<P>(path: P) -> Result<Self, Error>
+    where
+        P: AsRef<::std::ffi::OsStr>,
+    {
+        load_from_path(path)
+    }
+
+    /// See [`load_from_library`]
+    ///
+    /// # Safety
+    /// See [`load_from_library`]
+    pub unsafe fn load_from_library<L>(library: L) -> Result<Self, Error>
+    where
+        L: Into<libloading::Library>,
+    {
+        load_from_library(library)
+    }
+
+    /// See [`Adapter::create`]
+    pub fn create_adapter(&self, name: &str, tunnel_type: &str, guid: Option<u128>) -> Result<Arc<Adapter>> {
+        Adapter::create(self, name, tunnel_type, guid)
+    }
+
+    /// See [`Adapter::open`]
+    pub fn open_adapter(&self, name: &str) -> Result<Arc<Adapter>> {
+        Adapter::open(self, name)
+    }
+
+    /// See [`is_driver_available`]
+    pub fn is_driver_available(&self) -> bool {
+        is_driver_available(self)
+    }
+
+    /// See [`get_running_driver_version`]
+    pub fn driver_version(&self) -> Result<Version> {
+        get_running_driver_version(self)
+    }
+
+    /// See [`require_min_version`]
+    pub fn require_min_version(&self, major: u16, minor: u16) -> Result<()> {
+        require_min_version(self, major, minor)
+    }
+
+    /// See [`set_logger`]
+    pub fn set_logger(&self, f: wintun_raw::WINTUN_LOGGER_CALLBACK) {
+        set_logger(self, f)
+    }
+
+    /// See [`set_logger_callback`]
+    pub fn set_logger_callback(&self, callback: LoggerCallback) {
+        set_logger_callback(self, callback)
+    }
+
+    /// See [`reset_logger`]
+    pub fn reset_logger(&self) {
+        reset_logger(self)
+    }
+}
 
 use std::sync::Arc;
 
@@ -139,6 +273,9 @@ pub unsafe fn load() -> Result<Wintun, Error> {
 
 /// Attempts to load the Wintun library as a dynamic library from the given path.
 ///
+/// The path may be absolute, for example to load a wintun.dll placed next to the current
+/// executable or in a versioned subfolder. If the dll does not export every function these
+/// bindings need, [`Error::MissingSymbol`] names the first one that is missing.
 ///
 /// # Safety
 /// This function loads a dll file with the path provided.
@@ -153,7 +290,7 @@ pub unsafe fn load_from_path<P>(path: P) -> Result<Wintun, Error>
 where
     P: AsRef<::std::ffi::OsStr>,
 {
-    unsafe { Ok(Arc::new(wintun_raw::wintun::new(path)?)) }
+    unsafe { load_from_library(libloading::Library::new(path)?) }
 }
 
 /// Attempts to load the Wintun library from an existing [`libloading::Library`].
@@ -170,31 +307,155 @@ pub unsafe fn load_from_library<L>(library: L) -> Result<Wintun, Error>
 where
     L: Into<libloading::Library>,
 {
-    unsafe { Ok(Arc::new(wintun_raw::wintun::from_library(library)?)) }
+    let library = library.into();
+    for symbol in REQUIRED_SYMBOLS {
+        if unsafe { library.get::<*const ()>(symbol.as_bytes()) }.is_err() {
+            return Err(Error::MissingSymbol(symbol.trim_end_matches('\0')));
+        }
+    }
+    unsafe { Ok(Wintun(Arc::new(wintun_raw::wintun::from_library(library)?))) }
+}
+
+/// Loads wintun.dll, creates an adapter and starts a session on it in one call, for getting a
+/// tunnel running with as little setup as possible.
+///
+/// The dll is loaded from `dll_path`, or like [`load`] when it is `None`, and wintun's log
+/// messages are sent to the `log` crate with [`default_logger`] unless another logger was set,
+/// which does nothing without the `logging` feature.
+/// `capacity` is the ring capacity passed to [`Adapter::start_session`]. Use [`load_from_path`],
+/// [`AdapterBuilder`] and [`Adapter::start_session`] directly when more control is needed.
+///
+/// ```no_run
+/// let (adapter, session) = unsafe {
+///     wintun::quick_start(None, "Example", "Demo", wintun::MAX_RING_CAPACITY)
+/// }
+/// .unwrap();
+/// ```
+///
+/// # Safety
+/// This loads a dll file, see [`load_from_path`] for details
+pub unsafe fn quick_start(
+    dll_path: Option<&std::path::Path>,
+    pool: &str,
+    name: &str,
+    capacity: u32,
+) -> Result<(Arc<Adapter>, Session), Error> {
+    let wintun = match dll_path {
+        Some(path) => unsafe { load_from_path(path)? },
+        None => unsafe { load()? },
+    };
+    log::set_default_logger_if_unset(&wintun);
+    AdapterBuilder::new()
+        .name(name)
+        .pool(pool)
+        .ring_capacity(capacity)
+        .create_and_start(&wintun)
 }
 
+/// Null terminated names of every function [`wintun_raw::wintun`] loads
+const REQUIRED_SYMBOLS: &[&str] = &[
+    "WintunCreateAdapter\0",
+    "WintunCloseAdapter\0",
+    "WintunOpenAdapter\0",
+    "WintunGetAdapterLUID\0",
+    "WintunGetRunningDriverVersion\0",
+    "WintunDeleteDriver\0",
+    "WintunSetLogger\0",
+    "WintunStartSession\0",
+    "WintunEndSession\0",
+    "WintunGetReadWaitEvent\0",
+    "WintunReceivePacket\0",
+    "WintunReleaseReceivePacket\0",
+    "WintunAllocateSendPacket\0",
+    "WintunSendPacket\0",
+];
+
+/// The version of the running wintun driver, see [`get_running_driver_version`]
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
 pub struct Version {
     pub major: u16,
     pub minor: u16,
 }
 
+impl Version {
+    /// Unpacks a version in the format returned by WintunGetRunningDriverVersion, with the major
+    /// version in the high 16 bits and the minor version in the low 16 bits
+    ///
+    /// ```
+    /// let version = wintun::Version::from_packed(0x0000_000e);
+    /// assert_eq!(version, wintun::Version { major: 0, minor: 14 });
+    /// assert_eq!(version.to_packed(), 0x0000_000e);
+    /// assert_eq!(wintun::Version::from_packed(0x0001_0002).to_string(), "1.2");
+    /// ```
+    pub const fn from_packed(version: u32) -> Self {
+        Self {
+            major: (version >> 16) as u16,
+            minor: version as u16,
+        }
+    }
+
+    /// Packs this version in the format returned by WintunGetRunningDriverVersion
+    pub const fn to_packed(self) -> u32 {
+        ((self.major as u32) << 16) | self.minor as u32
+    }
+}
+
 impl std::fmt::Display for Version {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}.{}", self.major, self.minor)
     }
 }
 
+/// Parses a version in `major.minor` form, the same format [`Version`] is displayed in
+///
+/// ```
+/// let version: wintun::Version = "0.14".parse().unwrap();
+/// assert_eq!(version, wintun::Version { major: 0, minor: 14 });
+/// assert!("0".parse::<wintun::Version>().is_err());
+/// assert!("0.14.1".parse::<wintun::Version>().is_err());
+/// assert!("a.b".parse::<wintun::Version>().is_err());
+/// ```
+impl std::str::FromStr for Version {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::from(format!("Invalid version {:?}, expected major.minor", s));
+        let (major, minor) = s.split_once('.').ok_or_else(invalid)?;
+        Ok(Self {
+            major: major.parse().map_err(|_| invalid())?,
+            minor: minor.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
 /// Returns the major and minor version of the wintun driver
 pub fn get_running_driver_version(wintun: &Wintun) -> Result<Version> {
-    let version = unsafe { wintun.WintunGetRunningDriverVersion() };
+    let version = unsafe { wintun.raw().WintunGetRunningDriverVersion() };
     if version == 0 {
-        Err(util::get_last_error()?.into())
+        Err(Win32Error::last().into())
     } else {
-        let v = version.to_be_bytes();
-        Ok(Version {
-            major: u16::from_be_bytes([v[0], v[1]]),
-            minor: u16::from_be_bytes([v[2], v[3]]),
-        })
+        Ok(Version::from_packed(version))
+    }
+}
+
+/// Returns true if the wintun driver is currently loaded, meaning
+/// [`get_running_driver_version`] succeeds.
+///
+/// This separates the two reasons wintun can be unavailable: a missing or broken wintun.dll makes
+/// [`load`] and its variants fail with [`Error::LibLoading`] before this can be called, while a
+/// driver that is not installed or not running makes this return false. Wintun installs its driver
+/// when the first adapter is created, so false is expected on a machine that never created one
+/// and does not mean that creating an adapter will fail
+pub fn is_driver_available(wintun: &Wintun) -> bool {
+    unsafe { wintun.raw().WintunGetRunningDriverVersion() != 0 }
+}
+
+/// Returns [`Error::DriverTooOld`] if the running wintun driver is older than `major.minor`
+pub fn require_min_version(wintun: &Wintun, major: u16, minor: u16) -> Result<()> {
+    let found = get_running_driver_version(wintun)?;
+    let required = Version { major, minor };
+    if found < required {
+        return Err(Error::DriverTooOld { found, required });
     }
+    Ok(())
 }
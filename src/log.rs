@@ -1,37 +1,277 @@
 use crate::{util, wintun_raw, Wintun};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+#[cfg(feature = "logging")]
+use std::{
+    collections::VecDeque,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant, SystemTime},
+};
+
+/// The severity of a message wintun logged, as passed to a [`LoggerCallback`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LogLevel {
+    Info,
+    Warn,
+    Error,
+}
+
+impl LogLevel {
+    fn from_raw(level: wintun_raw::WINTUN_LOGGER_LEVEL) -> Self {
+        match level {
+            wintun_raw::WINTUN_LOGGER_LEVEL_WINTUN_LOG_WARN => LogLevel::Warn,
+            wintun_raw::WINTUN_LOGGER_LEVEL_WINTUN_LOG_ERR => LogLevel::Error,
+            //Wintun only defines these three levels
+            _ => LogLevel::Info,
+        }
+    }
+}
+
+#[cfg(feature = "logging")]
+impl From<LogLevel> for log::Level {
+    fn from(value: LogLevel) -> Self {
+        match value {
+            LogLevel::Info => log::Level::Info,
+            LogLevel::Warn => log::Level::Warn,
+            LogLevel::Error => log::Level::Error,
+        }
+    }
+}
+
+/// A user supplied sink for wintun's log messages, see [`set_logger_callback`]
+pub type LoggerCallback = Box<dyn Fn(LogLevel, &str) + Send + Sync>;
 
 /// Sets the logger wintun will use when logging. Maps to the WintunSetLogger C function
 pub fn set_logger(wintun: &Wintun, f: wintun_raw::WINTUN_LOGGER_CALLBACK) {
-    unsafe { wintun.WintunSetLogger(f) };
+    unsafe { wintun.raw().WintunSetLogger(f) };
 }
 
 pub fn reset_logger(wintun: &Wintun) {
     set_logger(wintun, None);
 }
 
+#[cfg(feature = "logging")]
 static SET_LOGGER: AtomicBool = AtomicBool::new(false);
 
-/// The logger that is active by default. Logs messages to the log crate
+#[cfg(feature = "logging")]
+static MIN_LEVEL: AtomicUsize = AtomicUsize::new(log::Level::Trace as usize);
+
+/// Drops wintun's messages that are less severe than `level` inside the logger callback, before
+/// they are converted from UTF-16. Cheaper than filtering in the `log` crate when the driver is
+/// chatty. Defaults to [`log::Level::Trace`], which lets every message through
+#[cfg(feature = "logging")]
+pub fn set_min_level(level: log::Level) {
+    MIN_LEVEL.store(level as usize, Ordering::Relaxed);
+}
+
+#[cfg(feature = "logging")]
+fn map_level(level: wintun_raw::WINTUN_LOGGER_LEVEL) -> log::Level {
+    match level {
+        wintun_raw::WINTUN_LOGGER_LEVEL_WINTUN_LOG_INFO => log::Level::Info,
+        wintun_raw::WINTUN_LOGGER_LEVEL_WINTUN_LOG_WARN => log::Level::Warn,
+        wintun_raw::WINTUN_LOGGER_LEVEL_WINTUN_LOG_ERR => log::Level::Error,
+        _ => log::Level::Debug,
+    }
+}
+
+#[cfg(feature = "logging")]
+fn is_enabled(level: wintun_raw::WINTUN_LOGGER_LEVEL) -> bool {
+    map_level(level) as usize <= MIN_LEVEL.load(Ordering::Relaxed)
+}
+
+/// A log message of wintun kept by [`capture_last`]
+#[cfg(feature = "logging")]
+#[derive(Clone, Debug)]
+pub struct LogEntry {
+    pub level: log::Level,
+    /// When wintun logged the message
+    pub timestamp: SystemTime,
+    pub message: String,
+}
+
+#[cfg(feature = "logging")]
+struct Capture {
+    capacity: usize,
+    entries: VecDeque<LogEntry>,
+}
+
+//Mirrors Capture::capacity so loggers can skip the lock while capturing is off
+#[cfg(feature = "logging")]
+static CAPTURE_CAPACITY: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(feature = "logging")]
+static CAPTURE: Mutex<Capture> = Mutex::new(Capture {
+    capacity: 0,
+    entries: VecDeque::new(),
+});
+
+/// Keeps the last `n` messages wintun logs in memory, for example to dump them from a crash
+/// handler with [`captured`]. A value of 0 stops capturing and drops the kept messages.
+///
+/// Capturing happens inside [`default_logger`], [`verbose_logger`] and the callback installed by
+/// [`set_logger_callback`] in addition to their normal output, so it works with whichever of them
+/// is active. Messages dropped by [`set_min_level`] are not captured
+#[cfg(feature = "logging")]
+pub fn capture_last(n: usize) {
+    let mut capture = CAPTURE.lock().unwrap_or_else(|e| e.into_inner());
+    capture.capacity = n;
+    let excess = capture.entries.len().saturating_sub(n);
+    capture.entries.drain(..excess);
+    CAPTURE_CAPACITY.store(n, Ordering::Relaxed);
+}
+
+/// Returns the messages kept by [`capture_last`], oldest first
+#[cfg(feature = "logging")]
+pub fn captured() -> Vec<LogEntry> {
+    let capture = CAPTURE.lock().unwrap_or_else(|e| e.into_inner());
+    capture.entries.iter().cloned().collect()
+}
+
+#[cfg(feature = "logging")]
+fn capture(level: wintun_raw::WINTUN_LOGGER_LEVEL, timestamp: wintun_raw::DWORD64, message: &str) {
+    if CAPTURE_CAPACITY.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+    let mut capture = CAPTURE.lock().unwrap_or_else(|e| e.into_inner());
+    if capture.capacity == 0 {
+        return;
+    }
+    if capture.entries.len() == capture.capacity {
+        capture.entries.pop_front();
+    }
+    capture.entries.push_back(LogEntry {
+        level: map_level(level),
+        timestamp: filetime_to_system_time(timestamp),
+        message: message.to_owned(),
+    });
+}
+
+/// Converts a timestamp in 100ns intervals since 1601-01-01 UTC, as wintun passes to its logger
+#[cfg(feature = "logging")]
+fn filetime_to_system_time(timestamp: u64) -> SystemTime {
+    //The number of 100ns intervals between 1601-01-01 and the unix epoch
+    const UNIX_EPOCH_AS_FILETIME: u64 = 116_444_736_000_000_000;
+    let since_epoch = timestamp.saturating_sub(UNIX_EPOCH_AS_FILETIME);
+    SystemTime::UNIX_EPOCH + Duration::from_nanos(since_epoch.saturating_mul(100))
+}
+
+/// The logger that is active by default. Logs messages to the log crate, or does nothing if the
+/// `logging` feature is disabled
 ///
 /// # Safety
 /// `message` must be a valid pointer that points to an aligned null terminated UTF-16 string
 pub unsafe extern "stdcall" fn default_logger(
     level: wintun_raw::WINTUN_LOGGER_LEVEL,
-    _timestamp: wintun_raw::DWORD64,
+    timestamp: wintun_raw::DWORD64,
     message: *const wintun_raw::WCHAR,
 ) {
-    //Wintun will always give us a valid UTF16 null termineted string
+    #[cfg(not(feature = "logging"))]
+    let _ = (level, timestamp, message);
+    #[cfg(feature = "logging")]
+    {
+        if !is_enabled(level) {
+            return;
+        }
+        //Wintun will always give us a valid UTF16 null termineted string
+        let utf8_msg = util::win_pwstr_to_string(message as *mut u16).unwrap_or_else(|e| e.to_string());
+        capture(level, timestamp, &utf8_msg);
+        match level {
+            wintun_raw::WINTUN_LOGGER_LEVEL_WINTUN_LOG_INFO => log::info!("WinTun: {}", utf8_msg),
+            wintun_raw::WINTUN_LOGGER_LEVEL_WINTUN_LOG_WARN => log::warn!("WinTun: {}", utf8_msg),
+            wintun_raw::WINTUN_LOGGER_LEVEL_WINTUN_LOG_ERR => log::error!("WinTun: {}", utf8_msg),
+            _ => log::debug!("WinTun: {} (with invalid log level {})", utf8_msg, level),
+        }
+    }
+}
+
+#[cfg(feature = "logging")]
+static VERBOSE_START: OnceLock<Instant> = OnceLock::new();
+
+/// Like [`default_logger`], but prefixes each message with the time since the verbose logger was
+/// registered and the id of the thread wintun logged from. Useful when debugging concurrency issues
+///
+/// # Safety
+/// `message` must be a valid pointer that points to an aligned null terminated UTF-16 string
+#[cfg(feature = "logging")]
+pub unsafe extern "stdcall" fn verbose_logger(
+    level: wintun_raw::WINTUN_LOGGER_LEVEL,
+    timestamp: wintun_raw::DWORD64,
+    message: *const wintun_raw::WCHAR,
+) {
+    if !is_enabled(level) {
+        return;
+    }
+    let elapsed = VERBOSE_START.get_or_init(Instant::now).elapsed();
+    let thread = std::thread::current().id();
     let utf8_msg = util::win_pwstr_to_string(message as *mut u16).unwrap_or_else(|e| e.to_string());
+    capture(level, timestamp, &utf8_msg);
+    let prefix = format!(
+        "[{:>6}.{:06}s {:?}]",
+        elapsed.as_secs(),
+        elapsed.subsec_micros(),
+        thread
+    );
     match level {
-        wintun_raw::WINTUN_LOGGER_LEVEL_WINTUN_LOG_INFO => log::info!("WinTun: {}", utf8_msg),
-        wintun_raw::WINTUN_LOGGER_LEVEL_WINTUN_LOG_WARN => log::warn!("WinTun: {}", utf8_msg),
-        wintun_raw::WINTUN_LOGGER_LEVEL_WINTUN_LOG_ERR => log::error!("WinTun: {}", utf8_msg),
-        _ => log::debug!("WinTun: {} (with invalid log level {})", utf8_msg, level),
+        wintun_raw::WINTUN_LOGGER_LEVEL_WINTUN_LOG_INFO => log::info!("{} WinTun: {}", prefix, utf8_msg),
+        wintun_raw::WINTUN_LOGGER_LEVEL_WINTUN_LOG_WARN => log::warn!("{} WinTun: {}", prefix, utf8_msg),
+        wintun_raw::WINTUN_LOGGER_LEVEL_WINTUN_LOG_ERR => log::error!("{} WinTun: {}", prefix, utf8_msg),
+        _ => log::debug!("{} WinTun: {} (with invalid log level {})", prefix, utf8_msg, level),
     }
 }
 
+/// Registers [`verbose_logger`] as the logger wintun will use. Creating or opening adapters
+/// afterwards will not replace it with [`default_logger`]
+#[cfg(feature = "logging")]
+pub fn set_verbose_logger(wintun: &Wintun) {
+    VERBOSE_START.get_or_init(Instant::now);
+    SET_LOGGER.store(true, Ordering::SeqCst);
+    set_logger(wintun, Some(verbose_logger));
+}
+
+static CALLBACK: RwLock<Option<LoggerCallback>> = RwLock::new(None);
+
+unsafe extern "stdcall" fn callback_logger(
+    level: wintun_raw::WINTUN_LOGGER_LEVEL,
+    timestamp: wintun_raw::DWORD64,
+    message: *const wintun_raw::WCHAR,
+) {
+    #[cfg(feature = "logging")]
+    if !is_enabled(level) {
+        return;
+    }
+    let utf8_msg = util::win_pwstr_to_string(message as *mut u16).unwrap_or_else(|e| e.to_string());
+    #[cfg(feature = "logging")]
+    capture(level, timestamp, &utf8_msg);
+    #[cfg(not(feature = "logging"))]
+    let _ = timestamp;
+    let level = LogLevel::from_raw(level);
+    //A poisoned lock only means a previous callback panicked, the stored callback is still usable
+    let callback = CALLBACK.read().unwrap_or_else(|e| e.into_inner());
+    if let Some(callback) = callback.as_ref() {
+        //Unwinding into the driver would abort the process. The panic hook already reported the
+        //panic, so the payload is dropped and only this message is lost
+        let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| callback(level, &utf8_msg)));
+    }
+}
+
+/// Routes wintun's log messages to `callback` instead of the `log` crate, for example to forward
+/// them to `tracing` or a channel. Calling this again replaces the previous callback. Creating or
+/// opening adapters afterwards will not replace it with [`default_logger`]. A panic in `callback`
+/// is caught before it reaches the driver. Unlike the other loggers this is available without the
+/// `logging` feature
+pub fn set_logger_callback(wintun: &Wintun, callback: LoggerCallback) {
+    *CALLBACK.write().unwrap_or_else(|e| e.into_inner()) = Some(callback);
+    #[cfg(feature = "logging")]
+    SET_LOGGER.store(true, Ordering::SeqCst);
+    set_logger(wintun, Some(callback_logger));
+}
+
+/// Registers [`default_logger`] unless another logger was set. Without the `logging` feature
+/// there is nothing to forward to, so no logger is registered
 pub(crate) fn set_default_logger_if_unset(wintun: &Wintun) {
+    #[cfg(not(feature = "logging"))]
+    let _ = wintun;
+    #[cfg(feature = "logging")]
     if SET_LOGGER
         .compare_exchange(false, true, Ordering::SeqCst, Ordering::Relaxed)
         .is_ok()
@@ -39,3 +279,29 @@ pub(crate) fn set_default_logger_if_unset(wintun: &Wintun) {
         set_logger(wintun, Some(default_logger));
     }
 }
+
+/// Stand ins for the macros of the `log` crate when the `logging` feature is disabled, imported as
+/// `log` by the modules that log. The arguments are type checked but never evaluated
+#[cfg(not(feature = "logging"))]
+pub(crate) mod noop {
+    macro_rules! noop_log {
+        ($($arg:tt)*) => {
+            if false {
+                let _ = format_args!($($arg)*);
+            }
+        };
+    }
+    pub(crate) use noop_log as debug;
+    pub(crate) use noop_log as error;
+    pub(crate) use noop_log as trace;
+    pub(crate) use noop_log as warn;
+}
+
+#[cfg(feature = "logging")]
+#[test]
+fn test_filetime_to_system_time() {
+    assert_eq!(filetime_to_system_time(0), SystemTime::UNIX_EPOCH);
+    let filetime = 116_444_736_000_000_000 + 1_700_000_000 * 10_000_000 + 5;
+    let expected = SystemTime::UNIX_EPOCH + Duration::new(1_700_000_000, 500);
+    assert_eq!(filetime_to_system_time(filetime), expected);
+}
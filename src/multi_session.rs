@@ -0,0 +1,112 @@
+use crate::{
+    session::{self, Session},
+    util, Error, Win32Error,
+};
+use std::{sync::Arc, time::Duration};
+use windows_sys::Win32::{
+    Foundation::{FALSE, HANDLE, WAIT_FAILED, WAIT_OBJECT_0, WAIT_TIMEOUT},
+    System::{
+        SystemServices::MAXIMUM_WAIT_OBJECTS,
+        Threading::{SetEvent, WaitForMultipleObjects, WaitForSingleObject, INFINITE},
+    },
+};
+
+/// The most sessions a [`MultiSession`] can wait on. WaitForMultipleObjects accepts at most
+/// `MAXIMUM_WAIT_OBJECTS` (64) handles and one of them is the shutdown event
+pub const MAX_MULTI_SESSIONS: usize = MAXIMUM_WAIT_OBJECTS as usize - 1;
+
+/// Waits on the read events of several sessions at once, so one thread can serve many tunnels
+///
+/// [`MultiSession::wait`] returns the index of a session that has packets, which are then received
+/// with [`Session::try_receive`] until it returns Ok(None). [`MultiSession::shutdown`] wakes all
+/// waiters without shutting the sessions themselves down
+pub struct MultiSession {
+    sessions: Vec<Arc<Session>>,
+    shutdown_event: util::OwnedEvent,
+}
+
+impl MultiSession {
+    /// Creates a [`MultiSession`] over `sessions`, which must hold between 1 and
+    /// [`MAX_MULTI_SESSIONS`] sessions
+    pub fn new(sessions: Vec<Arc<Session>>) -> Result<Self, Error> {
+        if sessions.is_empty() || sessions.len() > MAX_MULTI_SESSIONS {
+            let msg = format!(
+                "Cannot wait on {} sessions, between 1 and {} are supported",
+                sessions.len(),
+                MAX_MULTI_SESSIONS
+            );
+            return Err(msg.into());
+        }
+        Ok(Self {
+            sessions,
+            shutdown_event: util::OwnedEvent::new_manual_reset()?,
+        })
+    }
+
+    /// Returns the sessions in the order they were passed to [`MultiSession::new`]
+    pub fn sessions(&self) -> &[Arc<Session>] {
+        &self.sessions
+    }
+
+    /// Returns the session at `index`, as returned by [`MultiSession::wait`]
+    pub fn get(&self, index: usize) -> Option<&Arc<Session>> {
+        self.sessions.get(index)
+    }
+
+    /// Waits until one of the sessions may have packets to read or `timeout` elapses, waiting
+    /// forever if it is `None`. Returns the index of the ready session, or Ok(None) on timeout.
+    ///
+    /// When several sessions are ready the lowest index is returned, so drain the returned session
+    /// before waiting again to keep later sessions from being starved. Returns
+    /// [`Error::ShuttingDown`] once [`MultiSession::shutdown`] was called, even while sessions
+    /// keep receiving packets
+    pub fn wait(&self, timeout: Option<Duration>) -> Result<Option<usize>, Error> {
+        //Shutdown is checked first so a busy session cannot hide it
+        match unsafe { WaitForSingleObject(self.shutdown_event.raw(), 0) } {
+            WAIT_OBJECT_0 => return Err(Error::ShuttingDown),
+            WAIT_FAILED => return Err(Win32Error::last().into()),
+            _ => {}
+        }
+        //A packet kept by peek is not signaled by the driver again
+        let peeked = self
+            .sessions
+            .iter()
+            .position(|session| session.peeked.lock().unwrap_or_else(|e| e.into_inner()).is_some());
+        if peeked.is_some() {
+            return Ok(peeked);
+        }
+
+        //WaitForMultipleObjects reports the lowest signaled index, so shutdown goes first
+        let mut handles: Vec<HANDLE> = Vec::with_capacity(self.sessions.len() + 1);
+        handles.push(self.shutdown_event.raw());
+        for session in &self.sessions {
+            handles.push(session.get_read_wait_event()?);
+        }
+
+        let timeout = timeout.map_or(INFINITE, session::duration_to_wait_millis);
+        let result = unsafe {
+            //SAFETY: handles holds at most MAXIMUM_WAIT_OBJECTS valid handles
+            WaitForMultipleObjects(handles.len() as u32, handles.as_ptr(), FALSE, timeout)
+        };
+        let session_count = self.sessions.len() as u32;
+        match result.wrapping_sub(WAIT_OBJECT_0) {
+            _ if result == WAIT_FAILED => Err(Win32Error::last().into()),
+            _ if result == WAIT_TIMEOUT => Ok(None),
+            0 => Err(Error::ShuttingDown),
+            index if index <= session_count => Ok(Some(index as usize - 1)),
+            _ => {
+                //This should never happen
+                panic!("WaitForMultipleObjects returned unexpected value {:?}", result);
+            }
+        }
+    }
+
+    /// Wakes every thread blocked in [`MultiSession::wait`] and makes later calls return
+    /// [`Error::ShuttingDown`]. The sessions themselves keep running
+    pub fn shutdown(&self) -> Result<(), Error> {
+        if FALSE == unsafe { SetEvent(self.shutdown_event.raw()) } {
+            return Err(Win32Error::last().into());
+        }
+        Ok(())
+    }
+}
@@ -1,16 +1,29 @@
 use crate::session;
-use std::sync::Arc;
-
-pub(crate) enum Kind {
-    SendPacketPending, //Send packet type, but not sent yet
-    SendPacketSent,    //Send packet type - sent
-    ReceivePacket,
-}
-
-/// Represents a wintun packet
-pub struct Packet {
-    pub(crate) kind: Kind,
+use std::{mem::ManuallyDrop, sync::Arc};
 
+/// A packet received from the adapter, returned by [`crate::Session::try_receive`] and the other
+/// receive functions
+///
+/// A packet borrows a slot in its session's receive ring and keeps that session alive for as long
+/// as it exists. Its slot is handed back to wintun when it is dropped, so drop received packets
+/// promptly to keep the receive ring from filling up. A received packet can never be sent, copy
+/// it into a [`SendPacket`] to forward it.
+///
+/// Packets own a clone of the session's `Arc` instead of borrowing it, so dropping the last
+/// handle to a session while packets are alive defers ending the session until they are dropped
+/// as well, rather than leaving them pointing into freed rings:
+///
+/// ```no_run
+/// # use std::sync::Arc;
+/// # let wintun = unsafe { wintun::load() }.unwrap();
+/// # let adapter = wintun::Adapter::open(&wintun, "Demo").unwrap();
+/// let session = Arc::new(adapter.start_session(wintun::MAX_RING_CAPACITY).unwrap());
+/// let packet = session.receive_blocking().unwrap();
+/// drop(session);
+/// //The session ends once the packet is dropped
+/// println!("{} bytes", packet.bytes().len());
+/// ```
+pub struct RecvPacket {
     /// This lifetime is not actually 'static, however before you get your pitchforks let me explain...
     /// The bytes in this slice live for as long at the session that allocated them, or until
     /// WintunReleaseReceivePacket, or WintunSendPacket is called on them (whichever happens first).
@@ -39,9 +52,40 @@ pub struct Packet {
     /// Share ownership of session to prevent the session from being dropped before packets that
     /// belong to it
     pub(crate) session: Arc<session::Session>,
+
+    /// Position of this packet in the order packets were received from its session
+    pub(crate) seq: u64,
 }
 
-impl Packet {
+/// A packet allocated in the send ring by [`crate::Session::allocate_send_packet`]
+///
+/// Fill in its bytes and pass it to [`SendPacket::send`]. Sending consumes the packet, so it
+/// cannot be sent twice. Packets are sent in the order they were allocated, so an allocated packet
+/// holds up all packets allocated after it until it is sent or dropped; a packet that is dropped
+/// unsent is discarded as described in its `Drop` impl.
+///
+/// [`SendPacket`] and [`RecvPacket`] replace the `Packet` type of earlier versions, which checked
+/// a runtime `Kind` field on every send. Whether a packet can be sent is now decided by its type,
+/// so neither type carries a kind and sending has no branch on it. [`Packet`] remains as a
+/// deprecated alias of [`SendPacket`] for code that allocates and sends packets; code that
+/// receives packets has to name [`RecvPacket`] instead. There is no generic `Packet<State>`, since
+/// the two types differ in more than whether they can be sent
+pub struct SendPacket {
+    /// Not actually 'static, see the same field of [`RecvPacket`]. This region of the send ring
+    /// stays valid until WintunSendPacket is called on it, which consumes the packet
+    pub(crate) bytes: &'static mut [u8],
+
+    /// Share ownership of session to prevent the session from being dropped before packets that
+    /// belong to it
+    pub(crate) session: Arc<session::Session>,
+}
+
+/// The packet type of earlier versions, kept for code that allocates and sends packets. Received
+/// packets are [`RecvPacket`]s
+#[deprecated(note = "use `SendPacket` for allocated packets and `RecvPacket` for received ones")]
+pub type Packet = SendPacket;
+
+impl RecvPacket {
     /// Returns the bytes this packet holds as &mut.
     /// The lifetime of the bytes is tied to the lifetime of this packet.
     pub fn bytes_mut(&mut self) -> &mut [u8] {
@@ -53,35 +97,231 @@ impl Packet {
     pub fn bytes(&self) -> &[u8] {
         self.bytes
     }
+
+    /// Returns the sequence number of this packet, counting the packets received from its session
+    /// as [`RecvPacket`]s in the order they were taken off the ring.
+    ///
+    /// Numbering starts at 0 for every new session. Packets consumed without becoming a
+    /// [`RecvPacket`], for example through [`crate::Session::try_receive_into`] or the `Read` impl,
+    /// do not use up a number. Packets received concurrently on several threads are numbered in
+    /// the order they were wrapped, which can differ slightly from the order they arrived in
+    pub fn sequence(&self) -> u64 {
+        self.seq
+    }
+
+    /// Copies the bytes of this packet into a new vector.
+    ///
+    /// Unlike [`RecvPacket::bytes`] this copies, but the result no longer borrows from the ring.
+    /// Use `Vec::from(packet)` to also give the ring slot back right away
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.bytes.to_vec()
+    }
+
+    /// Returns the IP version from the high nibble of the first byte, usually 4 or 6, or `None`
+    /// for an empty packet.
+    ///
+    /// Wintun is a layer 3 tunnel, so packets are raw IP packets without an Ethernet header
+    pub fn ip_version(&self) -> Option<u8> {
+        self.bytes.first().map(|b| b >> 4)
+    }
+
+    /// Returns true if this is an IPv4 packet, see [`RecvPacket::ip_version`]
+    pub fn is_ipv4(&self) -> bool {
+        self.ip_version() == Some(4)
+    }
+
+    /// Returns true if this is an IPv6 packet, see [`RecvPacket::ip_version`]
+    pub fn is_ipv6(&self) -> bool {
+        self.ip_version() == Some(6)
+    }
+}
+
+impl SendPacket {
+    /// Returns the bytes this packet holds as &mut.
+    /// The lifetime of the bytes is tied to the lifetime of this packet.
+    pub fn bytes_mut(&mut self) -> &mut [u8] {
+        self.bytes
+    }
+
+    /// Returns an immutable reference to the bytes this packet holds.
+    /// The lifetime of the bytes is tied to the lifetime of this packet.
+    pub fn bytes(&self) -> &[u8] {
+        self.bytes
+    }
+
+    /// Sends this packet on the session that allocated it. Wraps WintunSendPacket
+    pub fn send(self) {
+        //Drop would send the packet a second time
+        let packet = ManuallyDrop::new(self);
+        //SAFETY: We share ownership of the session and exclusively own this region of the ring
+        //until it is sent here. The bytes are not accessed afterwards
+        unsafe {
+            packet
+                .session
+                .wintun
+                .raw()
+                .WintunSendPacket(packet.session.session.0, packet.bytes.as_ptr())
+        };
+        //SAFETY: packet is never used or dropped again, so the Arc is dropped exactly once
+        drop(unsafe { std::ptr::read(&packet.session) });
+    }
+}
+
+/// Copies the bytes out of the packet, then drops the packet so its ring slot is released
+/// immediately. The data can then be kept or moved to other threads independent of the session
+impl From<RecvPacket> for Vec<u8> {
+    fn from(packet: RecvPacket) -> Self {
+        packet.to_vec()
+    }
+}
+
+/// Derefs to the bytes this packet holds, so a packet can be used wherever a byte slice is expected
+impl std::ops::Deref for RecvPacket {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.bytes
+    }
+}
+
+/// Mutable access is available for received packets too, as each packet has exclusive access to
+/// its region of the ring. This allows rewriting a received packet in place, for example for NAT,
+/// before copying it into a send packet. Changes to a received packet are never sent anywhere
+impl std::ops::DerefMut for RecvPacket {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.bytes
+    }
 }
 
-impl Drop for Packet {
+impl std::ops::Deref for SendPacket {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.bytes
+    }
+}
+
+impl std::ops::DerefMut for SendPacket {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.bytes
+    }
+}
+
+impl Drop for RecvPacket {
     fn drop(&mut self) {
-        match self.kind {
-            Kind::ReceivePacket => {
-                unsafe {
-                    //SAFETY:
-                    //
-                    //  1. We share ownership of the session therefore it hasn't been dropped yet
-                    //  2. Bytes is valid because each packet holds exclusive access to a region of the
-                    //     ring buffer that the wintun session owns. We return that region of
-                    //     memory back to wintun here
-                    self.session
-                        .wintun
-                        .WintunReleaseReceivePacket(self.session.session.0, self.bytes.as_ptr())
-                };
-            }
-            Kind::SendPacketPending => {
-                //If someone allocates a packet with session.allocate_send_packet() and then it is
-                //dropped without being sent, this will hold up the send queue because wintun expects
-                //that every allocated packet is sent
-
-                #[cfg(feature = "panic_on_unsent_packets")]
-                panic!("Packet was never sent!");
-            }
-            Kind::SendPacketSent => {
-                //Nop
-            }
+        unsafe {
+            //SAFETY:
+            //
+            //  1. We share ownership of the session therefore it hasn't been dropped yet
+            //  2. Bytes is valid because each packet holds exclusive access to a region of the
+            //     ring buffer that the wintun session owns. We return that region of
+            //     memory back to wintun here
+            self.session.release_receive_packet(self.bytes)
+        };
+    }
+}
+
+impl Drop for SendPacket {
+    fn drop(&mut self) {
+        //If someone allocates a packet with session.allocate_send_packet() and then it is
+        //dropped without being sent, this will hold up the send queue because wintun expects
+        //that every allocated packet is sent
+
+        #[cfg(feature = "panic_on_unsent_packets")]
+        panic!("Packet was never sent!");
+
+        //Wintun has no way to free an allocated send packet other than sending it, so
+        //zero it out and send it anyway. A packet with IP version 0 is dropped by the
+        //driver, which reclaims the ring slot without anything reaching the network stack
+        #[cfg(not(feature = "panic_on_unsent_packets"))]
+        {
+            self.bytes.fill(0);
+            //SAFETY: We still share ownership of the session and exclusively own this region of
+            //the ring
+            unsafe {
+                self.session
+                    .wintun
+                    .raw()
+                    .WintunSendPacket(self.session.session.0, self.bytes.as_ptr())
+            };
         }
     }
 }
+
+/// A caller owned scratch buffer for [`crate::Session::send_buffered`]
+///
+/// Serialize each outgoing packet into the buffer, send it, then [`SendBuffer::clear`] it and
+/// reuse it for the next packet. The allocation is kept across packets, so forwarding does not
+/// touch the heap once the buffer has grown to the largest packet size.
+#[derive(Clone, Debug, Default)]
+pub struct SendBuffer {
+    buf: Vec<u8>,
+}
+
+impl SendBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an empty buffer that can hold `capacity` bytes without reallocating
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            buf: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Empties the buffer while keeping its allocation
+    pub fn clear(&mut self) {
+        self.buf.clear();
+    }
+
+    /// Replaces the contents of the buffer with `data`
+    pub fn set(&mut self, data: &[u8]) {
+        self.buf.clear();
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Gives access to the underlying vector, for example to resize it before serializing into it
+    pub fn as_mut_vec(&mut self) -> &mut Vec<u8> {
+        &mut self.buf
+    }
+}
+
+impl std::ops::Deref for SendBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+impl std::ops::DerefMut for SendBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.buf
+    }
+}
+
+impl From<Vec<u8>> for SendBuffer {
+    fn from(buf: Vec<u8>) -> Self {
+        Self { buf }
+    }
+}
+
+impl std::io::Write for SendBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_packets_own_their_session() {
+    //Packets that borrowed their session could not be 'static, or be moved to another thread
+    fn assert_owned<T: Send + 'static>() {}
+    assert_owned::<RecvPacket>();
+    assert_owned::<SendPacket>();
+}
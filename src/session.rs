@@ -1,14 +1,27 @@
+#[cfg(not(feature = "logging"))]
+use crate::log::noop as log;
 use crate::{
     packet,
     util::{self, UnsafeHandle},
-    wintun_raw, Adapter, Error, Wintun,
+    wintun_raw, Adapter, Error, Win32Error, Wintun,
+};
+use std::{
+    io::{IoSlice, Read, Write},
+    os::windows::io::RawHandle,
+    ptr, slice,
+    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+        Mutex, OnceLock,
+    },
+    time::{Duration, Instant},
 };
-use std::{ptr, slice, sync::Arc, sync::OnceLock};
 use windows_sys::Win32::{
     Foundation::{
-        CloseHandle, GetLastError, ERROR_NO_MORE_ITEMS, FALSE, HANDLE, WAIT_EVENT, WAIT_FAILED, WAIT_OBJECT_0,
+        GetHandleInformation, ERROR_BUFFER_OVERFLOW, ERROR_FILE_NOT_FOUND, ERROR_HANDLE_EOF, ERROR_NO_MORE_ITEMS,
+        FALSE, HANDLE, WAIT_FAILED, WAIT_OBJECT_0, WAIT_TIMEOUT,
     },
-    System::Threading::{SetEvent, WaitForMultipleObjects, INFINITE},
+    System::Threading::{SetEvent, WaitForMultipleObjects, WaitForSingleObject, INFINITE},
 };
 
 /// Wrapper around a <https://git.zx2c4.com/wintun/about/#wintun_session_handle>
@@ -20,147 +33,1310 @@ pub struct Session {
     pub(crate) wintun: Wintun,
 
     /// Windows event handle that is signaled by the wintun driver when data becomes available to
-    /// read
+    /// read. This handle is owned by the driver and must never be closed by us.
+    ///
+    /// It is cached for the lifetime of the session on purpose: wintun creates the event together
+    /// with the session in WintunStartSession and only closes it in WintunEndSession, so it cannot
+    /// go stale while the session is alive. If the driver is reloaded the session handle itself is
+    /// dead as well, and the only recovery is to drop this session and start a new one, which
+    /// fetches a fresh event
     pub(crate) read_event: OnceLock<HANDLE>,
 
-    /// Windows event handle that is signaled when [`Session::shutdown`] is called force blocking
-    /// readers to exit
-    pub(crate) shutdown_event: HANDLE,
+    /// The shutdown event and flag, shared with every [`ShutdownTrigger`] of this session
+    pub(crate) shutdown_state: Arc<ShutdownState>,
 
-    /// The adapter that owns this session
+    /// The adapter that owns this session. Holding a strong reference guarantees that
+    /// WintunCloseAdapter only runs after WintunEndSession, no matter in which order the user drops
+    /// the adapter and the session
     pub(crate) adapter: Arc<Adapter>,
+
+    /// A packet taken off the ring by [`Session::peek`] that has not been received yet
+    pub(crate) peeked: Mutex<Option<&'static mut [u8]>>,
+
+    /// The ring capacity passed to [`Adapter::start_session`]
+    pub(crate) capacity: u32,
+
+    /// Number of packets taken off the receive ring that have not been released back to wintun
+    pub(crate) pending_receives: AtomicUsize,
+
+    /// How often a receive that failed with a transient error is retried before giving up
+    pub(crate) max_receive_retries: AtomicU32,
+
+    /// Non blocking receive attempts before waiting, see [`Session::set_spin_count`]
+    pub(crate) spin_count: AtomicU32,
+
+    /// Sequence number of the next [`packet::RecvPacket`], see [`packet::RecvPacket::sequence`]
+    pub(crate) next_seq: AtomicU64,
+
+    /// Counters updated by [`Session::receive_blocking`]
+    #[cfg(feature = "metrics")]
+    pub(crate) metrics: Metrics,
+}
+
+#[cfg(feature = "metrics")]
+#[derive(Default)]
+pub(crate) struct Metrics {
+    spins: AtomicU64,
+    blocks: AtomicU64,
+    packets: AtomicU64,
+}
+
+/// Counters of [`Session::receive_blocking`] calls, enabled by the `metrics` feature
+///
+/// A high number of blocks relative to packets means most packets arrive after the spinning
+/// attempts gave up, so spinning longer will not help
+#[cfg(feature = "metrics")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    /// Non blocking receive attempts that found no packet
+    pub spins: u64,
+    /// Times the read event had to be waited on
+    pub blocks: u64,
+    /// Packets returned
+    pub packets: u64,
+}
+
+/// State behind [`Session::shutdown`], which outlives the session while a [`ShutdownTrigger`]
+/// refers to it
+pub(crate) struct ShutdownState {
+    /// Windows event handle that is signaled when [`Session::shutdown`] is called force blocking
+    /// readers to exit. This is a manual reset event so it stays signaled for every reader. It is
+    /// closed once the session and all triggers are dropped
+    pub(crate) event: util::OwnedEvent,
+
+    /// Set by the first call to [`Session::shutdown`]
+    pub(crate) is_shutdown: AtomicBool,
+
+    /// Number of threads currently waiting on the shutdown event, see [`Session::shutdown_join`]
+    pub(crate) waiters: AtomicUsize,
 }
 
+impl ShutdownState {
+    pub(crate) fn new(event: util::OwnedEvent) -> Self {
+        Self {
+            event,
+            is_shutdown: AtomicBool::new(false),
+            waiters: AtomicUsize::new(0),
+        }
+    }
+
+    fn fire(&self) -> Result<bool, Error> {
+        if self.is_shutdown.swap(true, Ordering::SeqCst) {
+            return Ok(false);
+        }
+        if FALSE == unsafe { SetEvent(self.event.raw()) } {
+            return Err(Win32Error::last().into());
+        }
+        Ok(true)
+    }
+
+    fn wait(&self, timeout: Option<Duration>) -> bool {
+        let timeout = timeout.map_or(INFINITE, duration_to_wait_millis);
+        match unsafe { WaitForSingleObject(self.event.raw(), timeout) } {
+            WAIT_OBJECT_0 => true,
+            //The event is set right after the flag, so the flag is the best answer if waiting failed
+            _ => self.is_shutdown.load(Ordering::SeqCst),
+        }
+    }
+}
+
+/// The alignment in bytes of every packet in wintun's rings, see
+/// [`Session::allocate_send_packet_aligned`]
+pub const PACKET_ALIGNMENT: usize = 4;
+
+/// Default for [`Session::set_spin_count`]
+pub(crate) const DEFAULT_SPIN_COUNT: u32 = 5;
+
+/// Default for [`Session::set_max_receive_retries`]
+pub(crate) const DEFAULT_RECEIVE_RETRIES: u32 = 3;
+
 impl Session {
     pub fn get_adapter(&self) -> Arc<Adapter> {
         self.adapter.clone()
     }
 
+    /// Returns the size in bytes of the ring buffers, as passed to [`Adapter::start_session`]
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    /// Sets how many times a receive that fails with a transient error, see
+    /// [`Error::is_transient`], is retried before the error is returned. Defaults to 3, and 0
+    /// disables retrying
+    pub fn set_max_receive_retries(&self, retries: u32) {
+        self.max_receive_retries.store(retries, Ordering::Relaxed);
+    }
+
+    /// Sets how many times the blocking receive functions try to receive without blocking before
+    /// they wait on the read event. Defaults to 5.
+    ///
+    /// Waiting costs a system call and a wakeup, so on a busy tunnel a higher count lowers latency
+    /// by catching packets that arrive in quick succession. On a mostly idle tunnel the extra
+    /// attempts just burn CPU before every wait, so a lower count is better there. At least one
+    /// attempt is always made, so 0 behaves like 1
+    pub fn set_spin_count(&self, count: u32) {
+        self.spin_count.store(count, Ordering::Relaxed);
+    }
+
+    fn spin_count(&self) -> u32 {
+        //The ring has to be checked after every wakeup, the event alone does not deliver a packet
+        self.spin_count.load(Ordering::Relaxed).max(1)
+    }
+
+    /// Returns the number of received packets that are still held by this process, including
+    /// a packet kept by [`Session::peek`].
+    ///
+    /// Wintun does not expose how full its rings are, so this only counts packets that were taken
+    /// off the receive ring and not yet dropped. Each of them keeps its slot in the ring occupied,
+    /// so a number that keeps growing means packets are held for too long and the ring will fill
+    /// up. Packets still waiting in the ring to be received are not counted, and the value may be
+    /// stale by the time it is read when other threads are receiving at the same time
+    pub fn approx_pending(&self) -> Result<usize, Error> {
+        Ok(self.pending_receives.load(Ordering::Relaxed))
+    }
+
+    /// Ends this session and starts a new one on the same adapter with a ring of `capacity` bytes.
+    ///
+    /// The adapter and its addresses, routes and other configuration are kept, only the rings are
+    /// replaced. Packets still in the old rings are lost. The spin count and receive retry settings
+    /// carry over to the new session. `capacity` is validated before the current session is ended,
+    /// so an invalid capacity leaves it running
+    pub fn reopen_with_capacity(self, capacity: u32) -> Result<Session, Error> {
+        crate::adapter::check_ring_capacity(capacity)?;
+        let adapter = self.adapter.clone();
+        let spin_count = self.spin_count.load(Ordering::Relaxed);
+        let max_receive_retries = self.max_receive_retries.load(Ordering::Relaxed);
+        //Wintun allows only one session per adapter, so this one has to end first
+        drop(self);
+        let session = adapter.start_session(capacity)?;
+        session.set_spin_count(spin_count);
+        session.set_max_receive_retries(max_receive_retries);
+        Ok(session)
+    }
+
+    /// Wraps a session handle that was started by other code, for example a C component, so it
+    /// can be used through this crate. `capacity` is the ring capacity the session was started
+    /// with and is validated like in [`Adapter::start_session`].
+    ///
+    /// The returned session takes ownership of the handle and ends it with WintunEndSession when
+    /// dropped. Like [`Adapter::start_session`] this fails with [`Error::SessionAlreadyActive`] if
+    /// `adapter` already has a session wrapped by this crate, unless that session was given out by
+    /// [`Session::into_raw`] and `handle` is the handle it returned.
+    ///
+    /// # Safety
+    /// `handle` must be a valid `WINTUN_SESSION_HANDLE` returned by WintunStartSession on `adapter`
+    /// using the same wintun.dll as `adapter`. It must not have been ended yet, and nothing else
+    /// may end it or wrap it again afterwards
+    pub unsafe fn from_raw(
+        adapter: &Arc<Adapter>,
+        handle: *mut std::ffi::c_void,
+        capacity: u32,
+    ) -> Result<Session, Error> {
+        if handle.is_null() {
+            return Err("Null session handle".into());
+        }
+        crate::adapter::check_ring_capacity(capacity)?;
+        //A session given out by into_raw still counts as active, so it is taken back over instead
+        let reclaimed = adapter
+            .raw_session
+            .compare_exchange(true, false, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok();
+        if !reclaimed
+            && adapter
+                .session_active
+                .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+                .is_err()
+        {
+            return Err(Error::SessionAlreadyActive);
+        }
+        let shutdown_event = match util::OwnedEvent::new_manual_reset() {
+            Ok(event) => event,
+            Err(err) => {
+                if reclaimed {
+                    adapter.raw_session.store(true, Ordering::Release);
+                } else {
+                    adapter.session_active.store(false, Ordering::Release);
+                }
+                return Err(err.into());
+            }
+        };
+        Ok(adapter.wrap_session(handle.cast(), capacity, shutdown_event))
+    }
+
+    /// Gives up ownership of the session handle without ending the session, the inverse of
+    /// [`Session::from_raw`].
+    ///
+    /// The caller becomes responsible for calling WintunEndSession on the handle, using the
+    /// wintun.dll this session was loaded from. A packet held by [`Session::peek`] is released
+    /// first.
+    ///
+    /// The session's reference to its adapter is returned alongside the handle. Keep it alive
+    /// until the handle is ended: dropping the last reference closes the adapter, which deletes an
+    /// adapter created by this process while the session is still running. The adapter keeps
+    /// counting as having a session, so [`Adapter::start_session`] fails with
+    /// [`Error::SessionAlreadyActive`] until the handle is given back to [`Session::from_raw`] and
+    /// that session is dropped. A handle ended outside this crate therefore leaves the adapter
+    /// unable to start another session
+    pub fn into_raw(self) -> (*mut std::ffi::c_void, Arc<Adapter>) {
+        //Drop would end the session, so only the fields that own resources are dropped below
+        let mut this = std::mem::ManuallyDrop::new(self);
+        let peeked = this.peeked.get_mut().unwrap_or_else(|e| e.into_inner());
+        if let Some(bytes) = peeked.take() {
+            unsafe { this.release_receive_packet(bytes) };
+        }
+        this.adapter.raw_session.store(true, Ordering::Release);
+        let handle = this.session.0;
+        //SAFETY: this is never used again and each field is dropped or moved out exactly once. The
+        //remaining fields do not own anything that needs to be dropped
+        let adapter = unsafe {
+            ptr::drop_in_place(&mut this.wintun);
+            ptr::drop_in_place(&mut this.shutdown_state);
+            ptr::drop_in_place(&mut this.peeked);
+            ptr::read(&this.adapter)
+        };
+        (handle.cast(), adapter)
+    }
+
+    /// Returns a [`PacketSender`] for sending from other threads without exposing the rest of the
+    /// session to them
+    pub fn sender(self: &Arc<Self>) -> PacketSender {
+        PacketSender { session: self.clone() }
+    }
+
+    /// Splits this session into a receiving half and a sending half that can be moved to
+    /// separate threads.
+    ///
+    /// Both halves share ownership of the underlying session, which is ended once both of them
+    /// (and any packets they produced) are dropped. Only the [`SessionReader`] can call
+    /// [`Session::shutdown`], so a writer cannot accidentally stop the reader.
+    pub fn split(self) -> (SessionReader, SessionWriter) {
+        let session = Arc::new(self);
+        (
+            SessionReader {
+                session: session.clone(),
+            },
+            SessionWriter { session },
+        )
+    }
+
     /// Allocates a send packet of the specified size. Wraps WintunAllocateSendPacket
     ///
-    /// All packets returned from this function must be sent using [`Session::send_packet`] because
+    /// All packets returned from this function must be sent using [`packet::SendPacket::send`] because
     /// wintun establishes the send packet order based on the invocation order of this function.
-    /// Therefore if a packet is allocated using this function, and then never sent, it will hold
-    /// up the send queue for all other packets allocated in the future. It is okay for the session
-    /// to shutdown with allocated packets that have not yet been sent
-    pub fn allocate_send_packet(self: &Arc<Self>, size: u16) -> Result<packet::Packet, Error> {
-        let ptr = unsafe { self.wintun.WintunAllocateSendPacket(self.session.0, size as u32) };
-        if ptr.is_null() {
-            Err(util::get_last_error()?.into())
-        } else {
-            Ok(packet::Packet {
-                //SAFETY: ptr is non null, aligned for u8, and readable for up to size bytes (which
-                //must be less than isize::MAX because bytes is a u16
-                bytes: unsafe { slice::from_raw_parts_mut(ptr, size as usize) },
-                session: self.clone(),
-                kind: packet::Kind::SendPacketPending,
-            })
+    /// Therefore if a packet is allocated using this function, and then held onto without being
+    /// sent, it will hold up the send queue for all other packets allocated in the future. Dropping
+    /// an unsent packet discards it and frees its place in the queue. It is okay for the session
+    /// to shutdown with allocated packets that have not yet been sent.
+    ///
+    /// Returns [`Error::RingFull`] if the send ring has no room, which is worth retrying, and
+    /// [`Error::ShuttingDown`] once the session is ending, which is not
+    pub fn allocate_send_packet(self: &Arc<Self>, size: u16) -> Result<packet::SendPacket, Error> {
+        let ptr = self.allocate_raw(size)?;
+        Ok(packet::SendPacket {
+            //SAFETY: ptr is non null, aligned for u8, and readable for up to size bytes (which
+            //must be less than isize::MAX because bytes is a u16
+            bytes: unsafe { slice::from_raw_parts_mut(ptr, size as usize) },
+            session: self.clone(),
+        })
+    }
+
+    /// Allocates a send packet of `size_of::<T>() + payload_len` bytes whose first byte is aligned
+    /// for `T`, so a `#[repr(C)]` header struct can be written to the start of the packet directly.
+    ///
+    /// Wintun places every packet in its rings at a multiple of [`PACKET_ALIGNMENT`] (4) bytes, so
+    /// types with an alignment of up to 4 are supported, which covers IP, TCP and UDP headers
+    /// made of `u8`, `u16` and `u32` fields. Types with a larger alignment are rejected before
+    /// anything is allocated. Otherwise this behaves like [`Session::allocate_send_packet`]
+    ///
+    /// ```no_run
+    /// # use std::sync::Arc;
+    /// # let wintun = unsafe { wintun::load() }.unwrap();
+    /// # let adapter = wintun::Adapter::open(&wintun, "Demo").unwrap();
+    /// # let session = Arc::new(adapter.start_session(wintun::MAX_RING_CAPACITY).unwrap());
+    /// #[repr(C)]
+    /// struct UdpHeader {
+    ///     src_port: u16,
+    ///     dst_port: u16,
+    ///     len: u16,
+    ///     checksum: u16,
+    /// }
+    /// let mut packet = session.allocate_send_packet_aligned::<UdpHeader>(32).unwrap();
+    /// let header = packet.bytes_mut().as_mut_ptr() as *mut UdpHeader;
+    /// //SAFETY: The packet is large enough for the header and aligned for it
+    /// unsafe { header.write(UdpHeader { src_port: 0, dst_port: 0, len: 0, checksum: 0 }) };
+    /// ```
+    pub fn allocate_send_packet_aligned<T>(self: &Arc<Self>, payload_len: usize) -> Result<packet::SendPacket, Error> {
+        let align = std::mem::align_of::<T>();
+        if align > PACKET_ALIGNMENT {
+            let msg = format!(
+                "Wintun aligns packets to {} bytes, which cannot hold a type aligned to {}",
+                PACKET_ALIGNMENT, align
+            );
+            return Err(msg.into());
         }
+        let len = std::mem::size_of::<T>().saturating_add(payload_len);
+        let ptr = self.allocate_raw(packet_size(len)?)?;
+        if !ptr.cast::<T>().is_aligned() {
+            //The driver broke its alignment, discard the packet as an unsent SendPacket would be
+            //SAFETY: See send_slice
+            unsafe { slice::from_raw_parts_mut(ptr, len) }.fill(0);
+            unsafe { self.wintun.raw().WintunSendPacket(self.session.0, ptr) };
+            return Err(format!("Wintun returned a send packet that is not aligned to {}", align).into());
+        }
+        Ok(packet::SendPacket {
+            //SAFETY: See allocate_send_packet
+            bytes: unsafe { slice::from_raw_parts_mut(ptr, len) },
+            session: self.clone(),
+        })
     }
 
-    /// Sends a packet previously allocated with [`Session::allocate_send_packet`]
-    pub fn send_packet(&self, mut packet: packet::Packet) {
-        assert!(matches!(packet.kind, packet::Kind::SendPacketPending));
+    /// Allocates `size` bytes from the send ring.
+    ///
+    /// A full ring is reported as [`Error::RingFull`] so callers can back off and retry, while a
+    /// session that is ending is reported as [`Error::ShuttingDown`]
+    fn allocate_raw(&self, size: u16) -> Result<*mut u8, Error> {
+        let ptr = unsafe { self.wintun.raw().WintunAllocateSendPacket(self.session.0, size as u32) };
+        if !ptr.is_null() {
+            return Ok(ptr);
+        }
+        match Win32Error::last().code() {
+            ERROR_BUFFER_OVERFLOW => Err(Error::RingFull),
+            ERROR_HANDLE_EOF => Err(Error::ShuttingDown),
+            code => Err(Win32Error::new(code).into()),
+        }
+    }
 
-        unsafe { self.wintun.WintunSendPacket(self.session.0, packet.bytes.as_ptr()) };
-        //Mark the packet at sent
-        packet.kind = packet::Kind::SendPacketSent;
+    /// Sends a packet previously allocated with [`Session::allocate_send_packet`], the same as
+    /// [`packet::SendPacket::send`]
+    pub fn send_packet(&self, packet: packet::SendPacket) {
+        packet.send()
+    }
+
+    /// Allocates a packet the size of `data`, copies `data` into it and sends it.
+    ///
+    /// This is the convenience path for sending a packet that is already serialized. Hot paths
+    /// can avoid the copy by serializing directly into the bytes of a packet obtained from
+    /// [`Session::allocate_send_packet`]. Slices larger than `u16::MAX` bytes are rejected with
+    /// an [`std::io::ErrorKind::InvalidInput`] error.
+    ///
+    /// When the send ring is full [`Error::RingFull`] is returned and nothing is sent, so the caller
+    /// can back off and retry. [`Error::ShuttingDown`] is returned once the session is ending
+    pub fn send_slice(&self, data: &[u8]) -> Result<(), Error> {
+        let ptr = self.allocate_raw(packet_size(data.len())?)?;
+        //SAFETY: ptr is non null, aligned for u8, and writable for up to size bytes. We own this
+        //region of the ring exclusively until it is sent below
+        unsafe { slice::from_raw_parts_mut(ptr, data.len()) }.copy_from_slice(data);
+        unsafe { self.wintun.raw().WintunSendPacket(self.session.0, ptr) };
+        Ok(())
+    }
+
+    /// Sends the IPv4 or IPv6 packet in `data`, like [`Session::send_slice`]. Enabled by the
+    /// `checksum` feature.
+    ///
+    /// If `recompute_checksums` is true the IPv4 header checksum and the TCP, UDP, ICMP or ICMPv6
+    /// checksum are recomputed in the send ring, which saves fixing them up by hand after
+    /// rewriting addresses or ports. The checksums of fragments and other transport protocols are
+    /// left as they are. Returns [`Error::MalformedPacket`] without sending anything if the
+    /// headers are truncated or inconsistent
+    #[cfg(feature = "checksum")]
+    pub fn send_ip_packet(&self, data: &[u8], recompute_checksums: bool) -> Result<(), Error> {
+        if !recompute_checksums {
+            return self.send_slice(data);
+        }
+        let checksums = crate::checksum::Checksums::parse(data)?;
+        let ptr = self.allocate_raw(packet_size(data.len())?)?;
+        //SAFETY: See send_slice
+        let bytes = unsafe { slice::from_raw_parts_mut(ptr, data.len()) };
+        bytes.copy_from_slice(data);
+        checksums.apply(bytes);
+        unsafe { self.wintun.raw().WintunSendPacket(self.session.0, ptr) };
+        Ok(())
+    }
+
+    /// Sends the concatenation of `bufs` as one packet, like [`Session::send_slice`] but without
+    /// joining them first. Useful when a header and its payload live in separate buffers.
+    ///
+    /// Every slice is copied straight into the send ring in order. A total length over `u16::MAX`
+    /// bytes is rejected with an [`std::io::ErrorKind::InvalidInput`] error before anything is
+    /// allocated
+    pub fn send_vectored(&self, bufs: &[IoSlice<'_>]) -> Result<(), Error> {
+        let len = bufs.iter().map(|buf| buf.len()).sum();
+        let ptr = self.allocate_raw(packet_size(len)?)?;
+        //SAFETY: See send_slice
+        copy_gathered(bufs, unsafe { slice::from_raw_parts_mut(ptr, len) });
+        unsafe { self.wintun.raw().WintunSendPacket(self.session.0, ptr) };
+        Ok(())
+    }
+
+    /// Sends each of `packets` with [`Session::send_slice`] and returns the outcome of every send
+    /// that was attempted, in the same order.
+    ///
+    /// Packets are handed to wintun in slice order, so a packet is never sent ahead of one before
+    /// it. If `stop_on_error` is true the batch stops at the first failure, whose error is the last
+    /// element of the returned vector, and the remaining packets are not attempted. Otherwise every
+    /// packet is attempted, so a packet after a [`Error::RingFull`] failure may still be sent once
+    /// wintun has made room. Retrying only the failed packets then reorders them after the rest
+    pub fn send_batch(&self, packets: &[&[u8]], stop_on_error: bool) -> Vec<Result<(), Error>> {
+        let mut results = Vec::with_capacity(packets.len());
+        for packet in packets {
+            let result = self.send_slice(packet);
+            let failed = result.is_err();
+            results.push(result);
+            if failed && stop_on_error {
+                break;
+            }
+        }
+        results
+    }
+
+    /// Like [`Session::send_slice`], but returns Ok(false) without sending anything when the send
+    /// ring is full instead of an error.
+    ///
+    /// Wintun sends packets strictly in order, so priorities have to be handled before packets
+    /// reach the ring. This lets a caller with its own priority queue drop or hold back low
+    /// priority packets under congestion instead of blocking
+    pub fn try_send_slice(&self, data: &[u8]) -> Result<bool, Error> {
+        match self.send_slice(data) {
+            Ok(()) => Ok(true),
+            Err(Error::RingFull) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Copies the contents of `buf` into the send ring as one packet, waiting for space if the
+    /// ring is full.
+    ///
+    /// This is meant for forwarding many packets through one reusable [`packet::SendBuffer`].
+    /// Unlike [`Session::send_slice`] a full ring is not reported to the caller; instead this
+    /// spins briefly, then yields and finally sleeps for increasing intervals of up to 800
+    /// microseconds until wintun has drained enough of the ring. Returns [`Error::ShuttingDown`]
+    /// if the session ends while waiting
+    pub fn send_buffered(&self, buf: &packet::SendBuffer) -> Result<(), Error> {
+        let mut attempt = 0u32;
+        loop {
+            match self.send_slice(buf) {
+                Err(Error::RingFull) => {}
+                result => return result,
+            }
+            if self.is_shutdown() {
+                return Err(Error::ShuttingDown);
+            }
+            match attempt {
+                0..=15 => std::hint::spin_loop(),
+                16..=31 => std::thread::yield_now(),
+                _ => std::thread::sleep(Duration::from_micros(50 << (attempt - 32).min(4))),
+            }
+            attempt = attempt.saturating_add(1);
+        }
     }
 
     /// Attempts to receive a packet from the virtual interface without blocking.
     /// If there are no packets currently in the receive queue, this function returns Ok(None)
     /// without blocking. If blocking until a packet is desirable, use [`Session::receive_blocking`]
-    pub fn try_receive(self: &Arc<Self>) -> Result<Option<packet::Packet>, Error> {
-        let mut size = 0u32;
+    ///
+    /// Any other driver failure is returned as [`Error::Io`] carrying the Win32 error code, see
+    /// [`Error::raw_os_error`]. Transient failures are retried first, see
+    /// [`Session::set_max_receive_retries`]
+    pub fn try_receive(self: &Arc<Self>) -> Result<Option<packet::RecvPacket>, Error> {
+        Ok(self.try_receive_raw()?.map(|bytes| packet::RecvPacket {
+            bytes,
+            session: self.clone(),
+            seq: self.next_seq.fetch_add(1, Ordering::Relaxed),
+        }))
+    }
 
-        let ptr = unsafe { self.wintun.WintunReceivePacket(self.session.0, &mut size as *mut u32) };
+    /// Receives up to `max` packets that are already in the receive queue without blocking,
+    /// appending them to `out` and returning how many were received.
+    ///
+    /// Each packet holds its slot in the ring until it is dropped, so a batch should be processed
+    /// and dropped (for example by calling `out.clear()`) before the next one is received.
+    /// Otherwise the ring fills up and the driver starts dropping incoming packets.
+    ///
+    /// If receiving fails after some packets were appended, their count is returned and the error
+    /// is left for the next call, which hits it again since a failed ring stays failed. An error is
+    /// only returned when `out` was not changed
+    pub fn try_receive_batch(self: &Arc<Self>, out: &mut Vec<packet::RecvPacket>, max: usize) -> Result<usize, Error> {
+        let mut count = 0;
+        while count < max {
+            match self.try_receive() {
+                Ok(Some(packet)) => out.push(packet),
+                Ok(None) => break,
+                Err(err) if count == 0 => return Err(err),
+                Err(err) => {
+                    log::debug!("Ending receive batch after {} packets: {}", count, err);
+                    break;
+                }
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
 
-        debug_assert!(size <= u16::MAX as u32);
-        if ptr.is_null() {
+    /// Receives the next packet into `buf` and releases its ring slot right away, returning the
+    /// length of the packet. Returns Ok(None) without blocking if there are no packets to receive.
+    ///
+    /// This suits callers that copy packets out anyway, for example to write them to a socket, as
+    /// no ring slot is held while they do slow I/O. If the packet does not fit,
+    /// [`Error::BufferTooSmall`] with the packet size is returned and the packet is kept, so the
+    /// next receive returns it again once `buf` has been grown
+    pub fn try_receive_into(&self, buf: &mut [u8]) -> Result<Option<usize>, Error> {
+        //Holding the lock keeps other receivers from taking a packet we put back
+        let mut peeked = self.peeked.lock().unwrap_or_else(|e| e.into_inner());
+        let bytes = match peeked.take() {
+            Some(bytes) => bytes,
+            None => match self.receive_from_ring()? {
+                Some(bytes) => bytes,
+                None => return Ok(None),
+            },
+        };
+        let len = bytes.len();
+        if len > buf.len() {
+            *peeked = Some(bytes);
+            return Err(Error::BufferTooSmall { needed: len });
+        }
+        buf[..len].copy_from_slice(bytes);
+        //SAFETY: bytes was handed to us by WintunReceivePacket on this session and is not
+        //accessed after being released
+        unsafe { self.release_receive_packet(bytes) };
+        Ok(Some(len))
+    }
+
+    /// Discards every packet that is currently in the receive queue, returning how many were
+    /// dropped. Does not block.
+    ///
+    /// Useful after a pause in which queued packets went stale, for example after roaming. Unlike
+    /// [`Session::shutdown_and_drain`] the session stays usable and the packets are not returned.
+    /// Packets arriving while this runs may also be discarded
+    pub fn flush_received(&self) -> Result<usize, Error> {
+        let mut dropped = 0;
+        while let Some(bytes) = self.try_receive_raw()? {
+            //SAFETY: bytes was handed to us by WintunReceivePacket on this session and is not
+            //accessed after being released
+            unsafe { self.release_receive_packet(bytes) };
+            dropped += 1;
+        }
+        Ok(dropped)
+    }
+
+    /// Calls `f` with the bytes of the next packet in the receive queue without consuming it, so
+    /// the next call to [`Session::try_receive`] or [`Session::receive_blocking`] returns the same
+    /// packet. Returns Ok(None) without blocking if there are no packets to peek at.
+    ///
+    /// Wintun has no way to look at a packet without taking it off the ring, so the peeked packet
+    /// is held by the session until it is received. A view is passed to `f` rather than returned so
+    /// that another thread cannot receive and release the packet while it is being looked at. `f`
+    /// must not receive from this session, as the next packet stays locked until `f` returns
+    pub fn peek<R>(&self, f: impl FnOnce(&[u8]) -> R) -> Result<Option<R>, Error> {
+        let mut peeked = self.peeked.lock().unwrap_or_else(|e| e.into_inner());
+        if peeked.is_none() {
+            *peeked = self.receive_from_ring()?;
+        }
+        Ok(peeked.as_deref().map(f))
+    }
+
+    /// Receives the next packet in the queue without wrapping it in a [`packet::RecvPacket`].
+    /// The caller is responsible for handing the returned bytes back to wintun via
+    /// WintunReleaseReceivePacket
+    fn try_receive_raw(&self) -> Result<Option<&'static mut [u8]>, Error> {
+        //A peeked packet was taken off the ring first, so it must be received first
+        if let Some(bytes) = self.peeked.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            return Ok(Some(bytes));
+        }
+        self.receive_from_ring()
+    }
+
+    fn receive_from_ring(&self) -> Result<Option<&'static mut [u8]>, Error> {
+        let mut size = 0u32;
+        let mut retries = 0;
+
+        let ptr = loop {
+            let ptr = unsafe {
+                self.wintun
+                    .raw()
+                    .WintunReceivePacket(self.session.0, &mut size as *mut u32)
+            };
+            if !ptr.is_null() {
+                break ptr;
+            }
             //Wintun returns ERROR_NO_MORE_ITEMS instead of blocking if packets are not available
-            match unsafe { GetLastError() } {
-                ERROR_NO_MORE_ITEMS => Ok(None),
-                e => Err(std::io::Error::from_raw_os_error(e as i32).into()),
+            let err: Error = match Win32Error::last().code() {
+                ERROR_NO_MORE_ITEMS => return Ok(None),
+                e => Win32Error::new(e).into(),
+            };
+            if !err.is_transient() || retries >= self.max_receive_retries.load(Ordering::Relaxed) {
+                return Err(err);
             }
-        } else {
-            Ok(Some(packet::Packet {
-                kind: packet::Kind::ReceivePacket,
-                //SAFETY: ptr is non null, aligned for u8, and readable for up to size bytes (which
-                //must be less than isize::MAX because bytes is a u16
-                bytes: unsafe { slice::from_raw_parts_mut(ptr, size as usize) },
-                session: self.clone(),
-            }))
+            retries += 1;
+            log::debug!("Retrying receive after transient error: {}", err);
+        };
+
+        if size > u16::MAX as u32 {
+            //Hand the packet back so the ring keeps moving, without ever forming a slice over it
+            unsafe { self.wintun.raw().WintunReleaseReceivePacket(self.session.0, ptr) };
+            return Err(Error::PacketTooLarge(size));
         }
+        self.pending_receives.fetch_add(1, Ordering::Relaxed);
+        //SAFETY: ptr is non null, aligned for u8, and readable for up to size bytes (which
+        //was checked to fit in a u16 above)
+        Ok(Some(unsafe { slice::from_raw_parts_mut(ptr, size as usize) }))
+    }
+
+    /// Hands a packet obtained from [`Session::receive_from_ring`] back to wintun
+    ///
+    /// # Safety
+    /// `bytes` must have been received on this session and must not be accessed afterwards
+    pub(crate) unsafe fn release_receive_packet(&self, bytes: &[u8]) {
+        self.wintun
+            .raw()
+            .WintunReleaseReceivePacket(self.session.0, bytes.as_ptr());
+        self.pending_receives.fetch_sub(1, Ordering::Relaxed);
     }
 
     /// Returns the low level read event handle that is signaled when more data becomes available
-    /// to read
+    /// to read.
+    ///
+    /// If WintunGetReadWaitEvent fails its Win32 error is returned. Only a valid handle is cached,
+    /// so a later call asks the driver again. Once fetched the handle stays valid until the
+    /// session is dropped. If waiting on it fails after the driver was reloaded, start a new
+    /// session rather than retrying on this one
     pub fn get_read_wait_event(&self) -> Result<HANDLE, Error> {
-        Ok(*self
-            .read_event
-            .get_or_init(|| unsafe { self.wintun.WintunGetReadWaitEvent(self.session.0) as _ }))
+        if let Some(event) = self.read_event.get() {
+            return Ok(*event);
+        }
+        let event: HANDLE = unsafe { self.wintun.raw().WintunGetReadWaitEvent(self.session.0) as _ };
+        if event == 0 {
+            //Make sure a failure is reported even if the driver did not set a last error
+            let err = Win32Error::last();
+            if err.code() != 0 {
+                return Err(err.into());
+            }
+            return Err("WintunGetReadWaitEvent returned a null handle".into());
+        }
+        Ok(*self.read_event.get_or_init(|| event))
+    }
+
+    /// Returns the read event handle as a [`RawHandle`], for registering the session with a
+    /// reactor such as `mio` or `polling` instead of blocking a dedicated thread.
+    ///
+    /// The handle is owned by the wintun driver and stays valid for the lifetime of this session.
+    /// Callers must not close it. The driver signals it when packets arrive in an empty ring, so
+    /// after each readiness notification drain the ring with [`Session::try_receive`] until it
+    /// returns Ok(None) before rearming the registration. See `examples/poller.rs` for doing this
+    /// with a `polling::Poller`
+    pub fn read_wait_event_raw(&self) -> Result<RawHandle, Error> {
+        Ok(self.get_read_wait_event()? as RawHandle)
     }
 
     /// Blocks until a packet is available, returning the next packet in the receive queue once this happens.
     /// If the session is closed via [`Session::shutdown`] all threads currently blocking inside this function
-    /// will return Err(())
-    pub fn receive_blocking(self: &Arc<Self>) -> Result<packet::Packet, Error> {
+    /// will return [`Error::ShuttingDown`]
+    pub fn receive_blocking(self: &Arc<Self>) -> Result<packet::RecvPacket, Error> {
         loop {
-            //Try 5 times to receive without blocking so we don't have to issue a syscall to wait
-            //for the event if packets are being received at a rapid rate
-            for _ in 0..5 {
+            //Try a few times to receive without blocking so we don't have to issue a syscall to
+            //wait for the event if packets are being received at a rapid rate
+            for _ in 0..self.spin_count() {
                 match self.try_receive() {
                     Err(err) => return Err(err),
-                    Ok(Some(packet)) => return Ok(packet),
+                    Ok(Some(packet)) => {
+                        #[cfg(feature = "metrics")]
+                        self.metrics.packets.fetch_add(1, Ordering::Relaxed);
+                        return Ok(packet);
+                    }
                     Ok(None) => {
                         //Try again
+                        #[cfg(feature = "metrics")]
+                        self.metrics.spins.fetch_add(1, Ordering::Relaxed);
                         continue;
                     }
                 }
             }
-            //Wait on both the read handle and the shutdown handle so that we stop when requested
-            let handles = [self.get_read_wait_event()?, self.shutdown_event];
-            let result = unsafe {
-                //SAFETY: We abide by the requirements of WaitForMultipleObjects, handles is a
-                //pointer to valid, aligned, stack memory
-                WaitForMultipleObjects(handles.len() as u32, &handles as _, FALSE, INFINITE)
-            };
-            const WAIT_OBJECT_1: WAIT_EVENT = WAIT_OBJECT_0 + 1;
-            match result {
-                WAIT_FAILED => return Err(util::get_last_error()?.into()),
-                WAIT_OBJECT_0 => {
-                    //We have data!
-                    continue;
+            #[cfg(feature = "metrics")]
+            self.metrics.blocks.fetch_add(1, Ordering::Relaxed);
+            self.wait_for_read_event(INFINITE)?;
+        }
+    }
+
+    /// Returns the counters of [`Session::receive_blocking`] calls on this session so far
+    #[cfg(feature = "metrics")]
+    pub fn metrics_snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            spins: self.metrics.spins.load(Ordering::Relaxed),
+            blocks: self.metrics.blocks.load(Ordering::Relaxed),
+            packets: self.metrics.packets.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns an iterator that blocks for each packet using [`Session::receive_blocking`].
+    /// The iterator ends once [`Session::shutdown`] is called, even if packets keep arriving. Any
+    /// other error is yielded as an item
+    pub fn iter(self: &Arc<Self>) -> ReceiveIter<'_> {
+        ReceiveIter {
+            session: self,
+            done: false,
+        }
+    }
+
+    /// Spawns a thread that receives packets with [`Session::receive_blocking`] and calls `f` with
+    /// each of them, for the common case of one dedicated reader per session.
+    ///
+    /// The thread returns Ok(()) once [`Session::shutdown`] is called, or the first error that is
+    /// not retried, which [`std::thread::JoinHandle::join`] hands back. Packets are passed by
+    /// value, so `f` decides how long their slot in the receive ring stays taken. This crate does
+    /// not set thread affinity; the thread is named `wintun-reader` so it can be found and pinned
+    /// from inside `f` or with platform tools. Panics if the thread cannot be spawned, like
+    /// [`std::thread::spawn`]
+    pub fn reader_thread<F>(self: &Arc<Self>, mut f: F) -> std::thread::JoinHandle<Result<(), Error>>
+    where
+        F: FnMut(packet::RecvPacket) + Send + 'static,
+    {
+        let session = self.clone();
+        std::thread::Builder::new()
+            .name("wintun-reader".to_owned())
+            .spawn(move || loop {
+                match session.receive_blocking() {
+                    Ok(packet) => f(packet),
+                    Err(Error::ShuttingDown) => return Ok(()),
+                    Err(err) => return Err(err),
                 }
-                WAIT_OBJECT_1 => {
-                    //Shutdown event triggered
-                    return Err(Error::ShuttingDown);
+            })
+            .expect("Failed to spawn the reader thread")
+    }
+
+    /// Blocks until a packet is available or `timeout` elapses, whichever happens first.
+    /// Returns Ok(None) if no packet arrived before the timeout. Timeouts longer than
+    /// `u32::MAX` milliseconds are clamped. Like [`Session::receive_blocking`], this returns
+    /// [`Error::ShuttingDown`] if the session is closed via [`Session::shutdown`]
+    pub fn receive_blocking_timeout(self: &Arc<Self>, timeout: Duration) -> Result<Option<packet::RecvPacket>, Error> {
+        //A deadline too far in the future to represent is as good as waiting the clamped maximum
+        let deadline = Instant::now().checked_add(timeout);
+        loop {
+            //Same fast path as `receive_blocking`
+            for _ in 0..self.spin_count() {
+                if let Some(packet) = self.try_receive()? {
+                    return Ok(Some(packet));
                 }
-                _ => {
-                    //This should never happen
-                    panic!("WaitForMultipleObjects returned unexpected value {:?}", result);
+            }
+            let remaining = deadline.map_or(timeout, |deadline| deadline.saturating_duration_since(Instant::now()));
+            if !self.wait_for_read_event(duration_to_wait_millis(remaining))? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Waits until the read event is signaled or `timeout` elapses, waiting forever if it is
+    /// `None`. Returns Ok(true) if data may be available to read and Ok(false) on timeout.
+    ///
+    /// Unlike [`Session::receive_blocking`] this does not wait on the shutdown event, so it is not
+    /// woken by [`Session::shutdown`]. It is meant as a building block for event loops that handle
+    /// cancellation themselves, which should then receive with [`Session::try_receive`]
+    pub fn wait_readable(&self, timeout: Option<Duration>) -> Result<bool, Error> {
+        //A packet kept by peek is not signaled by the driver again
+        if self.peeked.lock().unwrap_or_else(|e| e.into_inner()).is_some() {
+            return Ok(true);
+        }
+        let timeout = timeout.map_or(INFINITE, duration_to_wait_millis);
+        let event = self.get_read_wait_event()?;
+        match unsafe { WaitForSingleObject(event, timeout) } {
+            WAIT_FAILED => Err(Win32Error::last().into()),
+            WAIT_OBJECT_0 => Ok(true),
+            WAIT_TIMEOUT => Ok(false),
+            result => {
+                //This should never happen
+                panic!("WaitForSingleObject returned unexpected value {:?}", result);
+            }
+        }
+    }
+
+    /// Waits on both the read event and the shutdown event. Returns Ok(true) once data may be
+    /// available to read, Ok(false) if `timeout` milliseconds elapsed first, and
+    /// [`Error::ShuttingDown`] if [`Session::shutdown`] was called
+    pub(crate) fn wait_for_read_event(&self, timeout: u32) -> Result<bool, Error> {
+        self.wait_for_read_event_or_cancel(timeout, None)
+    }
+
+    /// Like [`Session::wait_for_read_event`], but also returns Ok(false) once the `cancel` event
+    /// is signaled. It is waited on first, so a cancelled wait does not consume the read event
+    pub(crate) fn wait_for_read_event_or_cancel(&self, timeout: u32, cancel: Option<HANDLE>) -> Result<bool, Error> {
+        //Wait on both the read handle and the shutdown handle so that we stop when requested
+        let mut handles: [HANDLE; 3] = [0; 3];
+        let first = match cancel {
+            Some(cancel) => {
+                handles[0] = cancel;
+                1
+            }
+            None => 0,
+        };
+        handles[first] = self.get_read_wait_event()?;
+        handles[first + 1] = self.shutdown_state.event.raw();
+        let count = first + 2;
+        //Tells shutdown_join that a reader is still blocked in here
+        self.shutdown_state.waiters.fetch_add(1, Ordering::SeqCst);
+        let result = unsafe {
+            //SAFETY: We abide by the requirements of WaitForMultipleObjects, handles is a
+            //pointer to valid, aligned, stack memory
+            WaitForMultipleObjects(count as u32, &handles as _, FALSE, timeout)
+        };
+        self.shutdown_state.waiters.fetch_sub(1, Ordering::SeqCst);
+        let first = first as u32;
+        match result.wrapping_sub(WAIT_OBJECT_0) {
+            _ if result == WAIT_FAILED => Err(Win32Error::last().into()),
+            _ if result == WAIT_TIMEOUT => Ok(false),
+            //The wait was cancelled
+            index if index < first => Ok(false),
+            //We have data!
+            index if index == first => Ok(true),
+            //Shutdown event triggered
+            index if index == first + 1 => Err(Error::ShuttingDown),
+            _ => {
+                //This should never happen
+                panic!("WaitForMultipleObjects returned unexpected value {:?}", result);
+            }
+        }
+    }
+
+    /// Cancels any active calls to [`Session::receive_blocking`] making them instantly return
+    /// [`Error::ShuttingDown`] so that session can be shutdown cleanly
+    ///
+    /// Returns Ok(true) if this call triggered the shutdown. Calling this again is harmless and
+    /// returns Ok(false)
+    pub fn shutdown(&self) -> Result<bool, Error> {
+        self.shutdown_state.fire()
+    }
+
+    /// Calls [`Session::shutdown`] and then waits up to `timeout` for every reader blocked in this
+    /// session to wake up, returning [`Error::ShutdownTimeout`] if some are still blocked.
+    ///
+    /// Readers acknowledge the shutdown by leaving the wait on the shutdown event. Every blocking
+    /// receive function, the [`Read`] impl and async receives count themselves while they wait, and
+    /// this polls that count until it reaches zero. [`Session::wait_readable`] does not wait on the
+    /// shutdown event and is not counted. As the event stays signaled, readers that start waiting
+    /// later return right away, so a timeout means a reader was not woken by the event
+    pub fn shutdown_join(&self, timeout: Duration) -> Result<(), Error> {
+        self.shutdown()?;
+        let deadline = Instant::now().checked_add(timeout);
+        loop {
+            let waiters = self.shutdown_state.waiters.load(Ordering::SeqCst);
+            if waiters == 0 {
+                return Ok(());
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(Error::ShutdownTimeout { waiters });
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Returns true once [`Session::shutdown`] has been called
+    pub fn is_shutdown(&self) -> bool {
+        self.shutdown_state.is_shutdown.load(Ordering::SeqCst)
+    }
+
+    /// Probes whether this session still looks usable, for a supervisor deciding whether to
+    /// recreate it, for example after the machine resumed from sleep.
+    ///
+    /// Returns Ok(false) if the session was shut down, the driver no longer reports a running
+    /// version, the read event handle became invalid, or the interface is gone or not up. None of
+    /// these touch the rings, so this is cheap enough to call periodically. A healthy result does
+    /// not guarantee that packets flow, so pair it with a traffic based check where possible.
+    ///
+    /// To recover from an unhealthy session:
+    /// 1. Shut it down and drop every clone of it, which ends the session
+    /// 2. Start a new session on the same adapter, which is often enough after a resume
+    /// 3. If the new session is not healthy either, drop the adapter as well, open or create it
+    ///    again, reapply its addresses and routes, and start a session on it
+    pub fn is_healthy(&self) -> Result<bool, Error> {
+        if self.is_shutdown() {
+            return Ok(false);
+        }
+        if unsafe { self.wintun.raw().WintunGetRunningDriverVersion() } == 0 {
+            return Ok(false);
+        }
+        //Waiting on the read event, even without a timeout, would consume a pending signal of the
+        //auto reset event and delay a reader, so only the handle itself is checked
+        let event = self.get_read_wait_event()?;
+        let mut flags = 0;
+        if FALSE == unsafe { GetHandleInformation(event, &mut flags) } {
+            return Ok(false);
+        }
+        match self.adapter.status() {
+            Ok(status) => Ok(status.oper_status == crate::OperStatus::Up),
+            Err(err) if err.raw_os_error() == Some(ERROR_FILE_NOT_FOUND as i32) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Blocks until [`Session::shutdown`] is called or `timeout` elapses, waiting forever if it is
+    /// `None`. Returns true if the session was shut down.
+    ///
+    /// This is the consumer side of [`Session::shutdown`], for a supervisor thread that should
+    /// wake up once the workers are told to stop instead of polling [`Session::is_shutdown`]
+    pub fn wait_for_shutdown(&self, timeout: Option<Duration>) -> bool {
+        self.shutdown_state.wait(timeout)
+    }
+
+    /// Returns a handle that shuts this session down when fired, for signal handlers or watchdogs
+    /// that should be able to stop the session without keeping it alive
+    pub fn shutdown_trigger(&self) -> ShutdownTrigger {
+        ShutdownTrigger {
+            state: self.shutdown_state.clone(),
+        }
+    }
+
+    /// Receives every packet that is already in the receive queue, then calls
+    /// [`Session::shutdown`]. Returns the drained packets so that packets which were in flight are
+    /// not lost during teardown. Use [`Session::shutdown`] to stop immediately instead.
+    ///
+    /// The session is shut down even if receiving fails. The packets drained until then are
+    /// returned together with the first error of receiving or shutting down
+    pub fn shutdown_and_drain(self: &Arc<Self>) -> (Vec<packet::RecvPacket>, Result<(), Error>) {
+        let mut drained = vec![];
+        let received = loop {
+            match self.try_receive() {
+                Ok(Some(packet)) => drained.push(packet),
+                Ok(None) => break Ok(()),
+                Err(err) => break Err(err),
+            }
+        };
+        let shutdown = self.shutdown().map(|_| ());
+        (drained, received.and(shutdown))
+    }
+}
+
+/// Iterator over received packets, created by [`Session::iter`]
+pub struct ReceiveIter<'a> {
+    session: &'a Arc<Session>,
+    done: bool,
+}
+
+impl Iterator for ReceiveIter<'_> {
+    type Item = Result<packet::RecvPacket, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        //receive_blocking only notices a shutdown once the ring is empty, which it never is on a
+        //busy tunnel
+        if self.done || self.session.is_shutdown() {
+            self.done = true;
+            return None;
+        }
+        match self.session.receive_blocking() {
+            Err(Error::ShuttingDown) => {
+                self.done = true;
+                None
+            }
+            result => Some(result),
+        }
+    }
+}
+
+impl std::iter::FusedIterator for ReceiveIter<'_> {}
+
+/// The receiving half of a [`Session`], created by [`Session::split`]
+pub struct SessionReader {
+    session: Arc<Session>,
+}
+
+impl SessionReader {
+    /// See [`Session::try_receive`]
+    pub fn try_receive(&self) -> Result<Option<packet::RecvPacket>, Error> {
+        self.session.try_receive()
+    }
+
+    /// See [`Session::try_receive_batch`]
+    pub fn try_receive_batch(&self, out: &mut Vec<packet::RecvPacket>, max: usize) -> Result<usize, Error> {
+        self.session.try_receive_batch(out, max)
+    }
+
+    /// See [`Session::receive_blocking`]
+    pub fn receive_blocking(&self) -> Result<packet::RecvPacket, Error> {
+        self.session.receive_blocking()
+    }
+
+    /// See [`Session::iter`]
+    pub fn iter(&self) -> ReceiveIter<'_> {
+        self.session.iter()
+    }
+
+    /// See [`Session::reader_thread`]
+    pub fn reader_thread<F>(&self, f: F) -> std::thread::JoinHandle<Result<(), Error>>
+    where
+        F: FnMut(packet::RecvPacket) + Send + 'static,
+    {
+        self.session.reader_thread(f)
+    }
+
+    /// See [`Session::receive_blocking_timeout`]
+    pub fn receive_blocking_timeout(&self, timeout: Duration) -> Result<Option<packet::RecvPacket>, Error> {
+        self.session.receive_blocking_timeout(timeout)
+    }
+
+    /// See [`Session::flush_received`]
+    pub fn flush_received(&self) -> Result<usize, Error> {
+        self.session.flush_received()
+    }
+
+    /// See [`Session::try_receive_into`]
+    pub fn try_receive_into(&self, buf: &mut [u8]) -> Result<Option<usize>, Error> {
+        self.session.try_receive_into(buf)
+    }
+
+    /// See [`Session::get_read_wait_event`]
+    pub fn get_read_wait_event(&self) -> Result<HANDLE, Error> {
+        self.session.get_read_wait_event()
+    }
+
+    /// See [`Session::wait_readable`]
+    pub fn wait_readable(&self, timeout: Option<Duration>) -> Result<bool, Error> {
+        self.session.wait_readable(timeout)
+    }
+
+    /// See [`Session::shutdown`]
+    pub fn shutdown(&self) -> Result<bool, Error> {
+        self.session.shutdown()
+    }
+
+    /// See [`Session::is_shutdown`]
+    pub fn is_shutdown(&self) -> bool {
+        self.session.is_shutdown()
+    }
+
+    /// See [`Session::wait_for_shutdown`]
+    pub fn wait_for_shutdown(&self, timeout: Option<Duration>) -> bool {
+        self.session.wait_for_shutdown(timeout)
+    }
+
+    /// See [`Session::is_healthy`]
+    pub fn is_healthy(&self) -> Result<bool, Error> {
+        self.session.is_healthy()
+    }
+
+    /// See [`Session::shutdown_trigger`]
+    pub fn shutdown_trigger(&self) -> ShutdownTrigger {
+        self.session.shutdown_trigger()
+    }
+
+    /// See [`Session::shutdown_join`]
+    pub fn shutdown_join(&self, timeout: Duration) -> Result<(), Error> {
+        self.session.shutdown_join(timeout)
+    }
+
+    /// See [`Session::shutdown_and_drain`]
+    pub fn shutdown_and_drain(&self) -> (Vec<packet::RecvPacket>, Result<(), Error>) {
+        self.session.shutdown_and_drain()
+    }
+}
+
+/// A cheaply cloneable handle that triggers [`Session::shutdown`] on the session it was obtained
+/// from, see [`Session::shutdown_trigger`]
+///
+/// Firing it after the session was dropped does nothing harmful
+#[derive(Clone)]
+pub struct ShutdownTrigger {
+    state: Arc<ShutdownState>,
+}
+
+impl ShutdownTrigger {
+    /// Shuts the session down, see [`Session::shutdown`]
+    pub fn fire(&self) -> Result<bool, Error> {
+        self.state.fire()
+    }
+
+    /// Returns true once the session has been shut down, see [`Session::is_shutdown`]
+    pub fn is_shutdown(&self) -> bool {
+        self.state.is_shutdown.load(Ordering::SeqCst)
+    }
+
+    /// Waits for the session to be shut down, see [`Session::wait_for_shutdown`]
+    pub fn wait_for_shutdown(&self, timeout: Option<Duration>) -> bool {
+        self.state.wait(timeout)
+    }
+}
+
+/// The sending half of a [`Session`], created by [`Session::split`]
+pub struct SessionWriter {
+    session: Arc<Session>,
+}
+
+impl SessionWriter {
+    /// See [`Session::allocate_send_packet`]
+    pub fn allocate_send_packet(&self, size: u16) -> Result<packet::SendPacket, Error> {
+        self.session.allocate_send_packet(size)
+    }
+
+    /// See [`Session::allocate_send_packet_aligned`]
+    pub fn allocate_send_packet_aligned<T>(&self, payload_len: usize) -> Result<packet::SendPacket, Error> {
+        self.session.allocate_send_packet_aligned::<T>(payload_len)
+    }
+
+    /// See [`Session::send_packet`]
+    pub fn send_packet(&self, packet: packet::SendPacket) {
+        self.session.send_packet(packet)
+    }
+
+    /// See [`Session::send_slice`]
+    pub fn send_slice(&self, data: &[u8]) -> Result<(), Error> {
+        self.session.send_slice(data)
+    }
+
+    /// See [`Session::try_send_slice`]
+    pub fn try_send_slice(&self, data: &[u8]) -> Result<bool, Error> {
+        self.session.try_send_slice(data)
+    }
+
+    /// See [`Session::send_vectored`]
+    pub fn send_vectored(&self, bufs: &[IoSlice<'_>]) -> Result<(), Error> {
+        self.session.send_vectored(bufs)
+    }
+
+    /// See [`Session::send_batch`]
+    pub fn send_batch(&self, packets: &[&[u8]], stop_on_error: bool) -> Vec<Result<(), Error>> {
+        self.session.send_batch(packets, stop_on_error)
+    }
+
+    /// See [`Session::send_ip_packet`]
+    #[cfg(feature = "checksum")]
+    pub fn send_ip_packet(&self, data: &[u8], recompute_checksums: bool) -> Result<(), Error> {
+        self.session.send_ip_packet(data, recompute_checksums)
+    }
+
+    /// See [`Session::send_buffered`]
+    pub fn send_buffered(&self, buf: &packet::SendBuffer) -> Result<(), Error> {
+        self.session.send_buffered(buf)
+    }
+}
+
+/// A cheaply cloneable handle that can only send packets, created by [`Session::sender`]
+///
+/// Meant for handing to worker threads that produce packets for one tunnel. The session ends once
+/// it and all senders are dropped
+#[derive(Clone)]
+pub struct PacketSender {
+    session: Arc<Session>,
+}
+
+impl PacketSender {
+    /// See [`Session::send_slice`]
+    pub fn send_slice(&self, data: &[u8]) -> Result<(), Error> {
+        self.session.send_slice(data)
+    }
+}
+
+/// Returns `len` as the u16 wintun takes for packet sizes
+fn packet_size(len: usize) -> Result<u16, Error> {
+    u16::try_from(len).map_err(|_| {
+        let msg = format!("Packet of {} bytes exceeds the wintun limit of {}", len, u16::MAX);
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, msg).into()
+    })
+}
+
+/// Copies `bufs` one after another into `dst`, which must be exactly as long as all of them
+fn copy_gathered(bufs: &[IoSlice<'_>], dst: &mut [u8]) {
+    let mut offset = 0;
+    for buf in bufs {
+        dst[offset..offset + buf.len()].copy_from_slice(buf);
+        offset += buf.len();
+    }
+    debug_assert_eq!(offset, dst.len());
+}
+
+/// Converts `duration` to a millisecond timeout for the Win32 wait functions, clamping long
+/// durations so they never turn into [`INFINITE`]
+pub(crate) fn duration_to_wait_millis(duration: Duration) -> u32 {
+    //INFINITE is u32::MAX so the largest finite timeout is one less
+    duration.as_millis().min((INFINITE - 1) as u128) as u32
+}
+
+/// Each call to [`Read::read`] yields exactly one IP packet, blocking until one is available.
+///
+/// If `buf` is too small to hold the whole packet an [`std::io::ErrorKind::InvalidInput`] error is
+/// returned and the packet is dropped rather than truncated, so pass a buffer of at least 0xFFFF
+/// bytes, the largest packet wintun delivers. This also applies to [`std::io::BufReader`], whose
+/// default buffer of 8 KiB is too small; create it with `BufReader::with_capacity(0xFFFF, session)`.
+/// An empty `buf` returns `Ok(0)` without receiving a packet. Once [`Session::shutdown`] is called
+/// reads return `Ok(0)`, signalling end of file.
+impl Read for Session {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            //Same fast path as `receive_blocking`
+            for _ in 0..self.spin_count() {
+                if let Some(bytes) = self.try_receive_raw()? {
+                    let len = bytes.len();
+                    let fits = len <= buf.len();
+                    if fits {
+                        buf[..len].copy_from_slice(bytes);
+                    }
+                    //SAFETY: bytes was handed to us by WintunReceivePacket on this session and is
+                    //not accessed after being released
+                    unsafe { self.release_receive_packet(bytes) };
+                    if !fits {
+                        let msg = format!("Packet of {} bytes does not fit in {} byte buffer", len, buf.len());
+                        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, msg));
+                    }
+                    return Ok(len);
                 }
             }
+            match self.wait_for_read_event(INFINITE) {
+                Ok(_) => continue,
+                Err(Error::ShuttingDown) => return Ok(0),
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+}
+
+/// Each call to [`Write::write`] sends `buf` as exactly one IP packet, so callers should pass a
+/// whole packet at a time. Buffers larger than `u16::MAX` bytes are rejected with
+/// [`std::io::ErrorKind::InvalidInput`]. A full send ring yields
+/// [`std::io::ErrorKind::WouldBlock`] and an ending session [`std::io::ErrorKind::BrokenPipe`].
+/// [`Write::write_vectored`] likewise sends all of its slices as one packet. Packets are handed to
+/// wintun immediately, so [`Write::flush`] does nothing.
+impl Write for Session {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self.send_slice(buf) {
+            Ok(()) => Ok(buf.len()),
+            Err(Error::ShuttingDown) => Err(std::io::ErrorKind::BrokenPipe.into()),
+            Err(err) => Err(err.into()),
         }
     }
 
-    /// Cancels any active calls to [`Session::receive_blocking`] making them instantly return Err(_) so that session can be shutdown cleanly
-    pub fn shutdown(&self) -> Result<(), Error> {
-        if FALSE == unsafe { SetEvent(self.shutdown_event) } {
-            return Err(util::get_last_error()?.into());
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> std::io::Result<usize> {
+        match self.send_vectored(bufs) {
+            Ok(()) => Ok(bufs.iter().map(|buf| buf.len()).sum()),
+            Err(Error::ShuttingDown) => Err(std::io::ErrorKind::BrokenPipe.into()),
+            Err(err) => Err(err.into()),
         }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
         Ok(())
     }
 }
 
 impl Drop for Session {
     fn drop(&mut self) {
-        if FALSE == unsafe { CloseHandle(self.shutdown_event) } {
-            let err = util::get_last_error();
-            log::error!("Failed to close handle of shutdown event: {:?}", err);
+        //Hand a packet that was peeked at but never received back before ending the session
+        let peeked = self.peeked.get_mut().unwrap_or_else(|e| e.into_inner());
+        if let Some(bytes) = peeked.take() {
+            unsafe { self.release_receive_packet(bytes) };
         }
 
-        unsafe { self.wintun.WintunEndSession(self.session.0) };
+        unsafe { self.wintun.raw().WintunEndSession(self.session.0) };
         self.session.0 = ptr::null_mut();
+        self.adapter.session_active.store(false, Ordering::Release);
     }
 }
+
+#[test]
+fn test_copy_gathered() {
+    let header = [0x45, 0x00, 0x00, 0x1c];
+    let payload = b"payload";
+    let mut packet = [0u8; 11];
+    copy_gathered(
+        &[IoSlice::new(&header), IoSlice::new(&[]), IoSlice::new(payload)],
+        &mut packet,
+    );
+    assert_eq!(&packet[..4], header);
+    assert_eq!(&packet[4..], payload);
+}
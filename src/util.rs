@@ -1,26 +1,38 @@
-use crate::Error;
-use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+#[cfg(not(feature = "logging"))]
+use crate::log::noop as log;
+use crate::{Error, Win32Error};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use windows_sys::{
     core::GUID,
     Win32::{
+        Devices::DeviceAndDriverInstallation::{
+            SetupDiCallClassInstaller, SetupDiDestroyDeviceInfoList, SetupDiEnumDeviceInfo, SetupDiGetClassDevsW,
+            SetupDiGetDeviceRegistryPropertyW, SetupDiOpenDevRegKey, SetupDiSetClassInstallParamsW, DICS_FLAG_GLOBAL,
+            DIF_REMOVE, DIGCF_PRESENT, DIREG_DRV, DI_REMOVEDEVICE_GLOBAL, GUID_DEVCLASS_NET, HDEVINFO,
+            SPDRP_HARDWAREID, SP_CLASSINSTALL_HEADER, SP_DEVINFO_DATA, SP_REMOVEDEVICE_PARAMS,
+        },
         Foundation::{
-            GetLastError, LocalFree, ERROR_BUFFER_OVERFLOW, ERROR_INSUFFICIENT_BUFFER, ERROR_SUCCESS, NO_ERROR,
-            WIN32_ERROR,
+            CloseHandle, GetLastError, LocalFree, ERROR_BUFFER_OVERFLOW, ERROR_INSUFFICIENT_BUFFER,
+            ERROR_NO_MORE_ITEMS, ERROR_SUCCESS, FALSE, HANDLE, INVALID_HANDLE_VALUE, NO_ERROR, TRUE, WIN32_ERROR,
         },
         NetworkManagement::{
             IpHelper::{
                 FreeMibTable, GetAdaptersAddresses, GetIfTable2, GetInterfaceInfo, DNS_INTERFACE_SETTINGS,
-                DNS_INTERFACE_SETTINGS_VERSION1, DNS_SETTING_NAMESERVER, GAA_FLAG_INCLUDE_GATEWAYS,
+                DNS_INTERFACE_SETTINGS_VERSION1, DNS_SETTING_IPV6, DNS_SETTING_NAMESERVER, GAA_FLAG_INCLUDE_GATEWAYS,
                 GAA_FLAG_INCLUDE_PREFIX, IF_TYPE_ETHERNET_CSMACD, IF_TYPE_IEEE80211, IP_ADAPTER_ADDRESSES_LH,
                 IP_ADAPTER_INDEX_MAP, IP_INTERFACE_INFO, MIB_IF_ROW2, MIB_IF_TABLE2,
             },
-            Ndis::{IfOperStatusUp, NET_LUID_LH},
+            Ndis::IfOperStatusUp,
+        },
+        Networking::WinSock::{
+            AF_INET, AF_INET6, AF_UNSPEC, SOCKADDR, SOCKADDR_IN, SOCKADDR_IN6, SOCKADDR_INET, SOCKET_ADDRESS,
         },
-        Networking::WinSock::{AF_INET, AF_INET6, AF_UNSPEC, SOCKADDR, SOCKADDR_IN, SOCKADDR_IN6, SOCKET_ADDRESS},
         System::{
             Com::StringFromGUID2,
             Diagnostics::Debug::{FormatMessageW, FORMAT_MESSAGE_ALLOCATE_BUFFER, FORMAT_MESSAGE_FROM_SYSTEM},
+            Registry::{RegCloseKey, RegQueryValueExW, KEY_QUERY_VALUE},
             SystemServices::{LANG_NEUTRAL, SUBLANG_DEFAULT},
+            Threading::CreateEventA,
         },
     },
 };
@@ -65,6 +77,57 @@ pub(crate) struct UnsafeHandle<T>(pub T);
 unsafe impl<T> Send for UnsafeHandle<T> {}
 unsafe impl<T> Sync for UnsafeHandle<T> {}
 
+/// A Win32 event created by us, which is closed when this is dropped.
+/// Driver owned handles such as the session read event must not be wrapped in this
+pub(crate) struct OwnedEvent(HANDLE);
+
+/// Event handles can be signaled and waited on from any thread
+unsafe impl Send for OwnedEvent {}
+unsafe impl Sync for OwnedEvent {}
+
+impl OwnedEvent {
+    /// Creates an unnamed, initially unsignaled event that stays signaled until it is reset
+    pub(crate) fn new_manual_reset() -> std::io::Result<Self> {
+        let handle = unsafe { CreateEventA(std::ptr::null_mut(), TRUE, FALSE, std::ptr::null_mut()) };
+        if handle == 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(Self(handle))
+    }
+
+    pub(crate) fn raw(&self) -> HANDLE {
+        self.0
+    }
+}
+
+impl Drop for OwnedEvent {
+    fn drop(&mut self) {
+        if FALSE == unsafe { CloseHandle(self.0) } {
+            log::error!("Failed to close event handle: {}", Win32Error::last());
+        }
+    }
+}
+
+/// Formats a GUID in the `{XXXXXXXX-XXXX-XXXX-XXXX-XXXXXXXXXXXX}` form used by the registry
+pub(crate) fn guid_u128_to_string(guid: u128) -> String {
+    format!(
+        "{{{:08X}-{:04X}-{:04X}-{:04X}-{:012X}}}",
+        guid >> 96,
+        (guid >> 80) & 0xffff,
+        (guid >> 64) & 0xffff,
+        (guid >> 48) & 0xffff,
+        guid & 0xffff_ffff_ffff
+    )
+}
+
+#[test]
+fn test_guid_u128_to_string() {
+    let guid = GUID::from_u128(0x6ba7b810_9dad_11d1_80b4_00c04fd430c8);
+    let expected = "{6BA7B810-9DAD-11D1-80B4-00C04FD430C8}";
+    assert_eq!(guid_u128_to_string(win_guid_to_u128(&guid)), expected);
+    assert_eq!(guid_to_win_style_string(&guid).unwrap(), expected);
+}
+
 pub(crate) fn guid_to_win_style_string(guid: &GUID) -> Result<String, Error> {
     let mut buffer = [0u16; 40];
     unsafe { StringFromGUID2(guid, &mut buffer as *mut u16, buffer.len() as i32) };
@@ -117,14 +180,32 @@ pub fn get_active_network_interface_gateways() -> std::io::Result<Vec<IpAddr>> {
     Ok(addrs)
 }
 
-pub(crate) fn set_interface_dns_servers(interface: GUID, dns: &[IpAddr]) -> crate::Result<()> {
+/// Sets the alias of the network connection with `guid` to the zero terminated `name`, the way
+/// wintun names the adapters it creates
+pub(crate) fn set_connection_name(guid: GUID, name: &[u16]) -> crate::Result<()> {
+    // NciSetConnectionName is not part of the SDK, so it is loaded from nci.dll at runtime
+    type TheFn = unsafe extern "system" fn(guid: *const GUID, name: *const u16) -> WIN32_ERROR;
+    let library = unsafe { ::libloading::Library::new("nci.dll")? };
+    let func: TheFn = unsafe { library.get(b"NciSetConnectionName\0").map(|sym| *sym)? };
+    match unsafe { func(&guid, name.as_ptr()) } {
+        0 => Ok(()),
+        e => Err(Win32Error::new(e).into()),
+    }
+}
+
+/// Sets the IPv4 or IPv6 DNS servers of `interface`, an empty list clears them
+pub(crate) fn set_interface_dns_servers(interface: GUID, dns: &[IpAddr], ipv6: bool) -> crate::Result<()> {
     // format L"1.1.1.1,8.8.8.8", or L"1.1.1.1 8.8.8.8".
     let dns = dns.iter().map(|ip| ip.to_string()).collect::<Vec<_>>().join(",");
     let dns = dns.encode_utf16().chain(std::iter::once(0)).collect::<Vec<_>>();
 
     let settings = DNS_INTERFACE_SETTINGS {
         Version: DNS_INTERFACE_SETTINGS_VERSION1,
-        Flags: DNS_SETTING_NAMESERVER as _,
+        Flags: if ipv6 {
+            (DNS_SETTING_NAMESERVER | DNS_SETTING_IPV6) as _
+        } else {
+            DNS_SETTING_NAMESERVER as _
+        },
         NameServer: dns.as_ptr() as _,
         Domain: std::ptr::null_mut(),
         SearchList: std::ptr::null_mut(),
@@ -148,16 +229,18 @@ pub(crate) fn set_interface_dns_servers(interface: GUID, dns: &[IpAddr]) -> crat
     }
 }
 
-pub(crate) fn set_adapter_dns_servers(adapter: &str, dns: &[IpAddr]) -> crate::Result<()> {
-    if dns.is_empty() {
-        return Ok(());
-    }
-    let ip_str = if dns[0].is_ipv4() { "ipv4" } else { "ipv6" };
+/// Same as [`set_interface_dns_servers`] but using `netsh`, for Windows versions before 10
+pub(crate) fn set_adapter_dns_servers(adapter: &str, dns: &[IpAddr], ipv6: bool) -> crate::Result<()> {
+    let ip_str = if ipv6 { "ipv6" } else { "ipv4" };
 
     // netsh interface ipv4 set dns name="MyAdapter" source="static" address="8.8.8.8"
     // netsh interface ipv4 add dns name="MyAdapter" index=2 address="8.8.4.4"
+    // An address of "none" clears the static DNS servers
     let name = format!("name=\"{}\"", adapter);
-    let addr = format!("address=\"{}\"", dns[0]);
+    let addr = match dns.first() {
+        Some(first) => format!("address=\"{}\"", first),
+        None => "address=none".to_string(),
+    };
     let args = vec!["interface", ip_str, "set", "dns", &name, "source=\"static\"", &addr];
     run_command("netsh", &args)?;
     let mut index = 2;
@@ -199,6 +282,28 @@ pub(crate) unsafe fn sockaddr_in6_to_socket_addr(sockaddr_in6: &SOCKADDR_IN6) ->
     SocketAddr::new(ip, port)
 }
 
+pub(crate) fn ipv4_to_sockaddr_inet(address: Ipv4Addr) -> SOCKADDR_INET {
+    let mut sockaddr: SOCKADDR_INET = unsafe { std::mem::zeroed() };
+    sockaddr.Ipv4.sin_family = AF_INET;
+    sockaddr.Ipv4.sin_addr.S_un.S_addr = u32::from_ne_bytes(address.octets());
+    sockaddr
+}
+
+pub(crate) fn ipv6_to_sockaddr_inet(address: Ipv6Addr) -> SOCKADDR_INET {
+    //Zeroing also leaves the port, flow info and scope id at zero
+    let mut sockaddr: SOCKADDR_INET = unsafe { std::mem::zeroed() };
+    sockaddr.Ipv6.sin6_family = AF_INET6;
+    sockaddr.Ipv6.sin6_addr.u.Byte = address.octets();
+    sockaddr
+}
+
+pub(crate) fn ipaddr_to_sockaddr_inet(address: IpAddr) -> SOCKADDR_INET {
+    match address {
+        IpAddr::V4(address) => ipv4_to_sockaddr_inet(address),
+        IpAddr::V6(address) => ipv6_to_sockaddr_inet(address),
+    }
+}
+
 pub(crate) fn get_adapters_addresses<F>(mut callback: F) -> Result<(), Error>
 where
     F: FnMut(IP_ADAPTER_ADDRESSES_LH) -> Result<(), Error>,
@@ -357,44 +462,20 @@ pub fn format_message(error_code: u32) -> Result<String, Box<dyn std::error::Err
         )
     };
     if chars_written == 0 {
-        return Ok(get_last_error()?);
+        return match Win32Error::last() {
+            err if err.code() == 0 => Ok("No error".to_string()),
+            err => Err(std::io::Error::from(err).into()),
+        };
     }
     let result = unsafe { win_pwstr_to_string(buf)? };
     // Win32 returns the same handle if LocalFree fails.
     if unsafe { !LocalFree(buf as *mut _).is_null() } {
-        log::trace!("LocalFree failed: {:?}", get_last_error());
+        log::trace!("LocalFree failed: {}", Win32Error::last());
     }
 
     Ok(result)
 }
 
-pub(crate) fn get_last_error() -> std::io::Result<String> {
-    get_os_error_from_id(unsafe { GetLastError() as _ })?;
-    Ok("No error".to_string())
-}
-
-pub(crate) fn get_os_error_from_id(id: i32) -> std::io::Result<()> {
-    match id {
-        0 => Ok(()),
-        e => Err(std::io::Error::from_raw_os_error(e)),
-    }
-}
-
-pub(crate) fn set_adapter_mtu(name: &str, mtu: usize) -> std::io::Result<()> {
-    // command line: `netsh interface ipv4 set subinterface "MyAdapter" mtu=1500 store=persistent`
-    let args = &[
-        "interface",
-        "ipv4",
-        "set",
-        "subinterface",
-        &format!("\"{}\"", name),
-        &format!("mtu={}", mtu),
-        "store=persistent",
-    ];
-    run_command("netsh", args)?;
-    Ok(())
-}
-
 /// Runs a command and returns an error if the command fails, just convenience for users.
 #[doc(hidden)]
 pub fn run_command(command: &str, args: &[&str]) -> std::io::Result<Vec<u8>> {
@@ -411,7 +492,14 @@ pub fn run_command(command: &str, args: &[&str]) -> std::io::Result<Vec<u8>> {
     Ok(out.stdout)
 }
 
-pub(crate) fn get_adapter_mtu(luid: &NET_LUID_LH) -> std::io::Result<usize> {
+/// Calls `callback` with every row of the interface table returned by GetIfTable2, stopping
+/// early once it returns false.
+///
+/// A panic in `callback` is caught so the table is always freed, then resumed once it is
+pub(crate) fn for_each_if_row<F>(mut callback: F) -> std::io::Result<()>
+where
+    F: FnMut(&MIB_IF_ROW2) -> bool,
+{
     unsafe {
         let mut if_table: *mut MIB_IF_TABLE2 = std::ptr::null_mut();
         match GetIfTable2(&mut if_table as *mut *mut _) {
@@ -420,40 +508,134 @@ pub(crate) fn get_adapter_mtu(luid: &NET_LUID_LH) -> std::io::Result<usize> {
         }
 
         let num_entries = (*if_table).NumEntries as usize;
-        let mut mtu = None;
-
-        let luid = &luid.Info as *const _ as *const _NET_LUID_LH_INFO;
-
         let table = &(*if_table).Table as *const MIB_IF_ROW2;
         let table = std::slice::from_raw_parts(table, num_entries);
-
-        for if_row in table {
-            let info = &if_row.InterfaceLuid.Info as *const _ as *const _NET_LUID_LH_INFO;
-
-            if (*info).IfType() == (*luid).IfType() && (*info).NetLuidIndex() == (*luid).NetLuidIndex() {
-                mtu = Some(if_row.Mtu as usize);
-                break;
+        let mut panic = None;
+        for row in table {
+            match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| callback(row))) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(payload) => {
+                    panic = Some(payload);
+                    break;
+                }
             }
         }
 
         // There is no return value for `FreeMibTable`, so we ignore the return value
         FreeMibTable(if_table as *mut _);
-        mtu.ok_or(std::io::Error::new(std::io::ErrorKind::NotFound, "Adapter not found"))
+        if let Some(payload) = panic {
+            std::panic::resume_unwind(payload);
+        }
     }
+    Ok(())
+}
+
+/// Hardware id of the devices created by the wintun driver
+const WINTUN_HARDWARE_ID: &str = "Wintun";
+
+/// Returns true if the hardware ids of the device include [`WINTUN_HARDWARE_ID`]
+fn is_wintun_device(dev_info: HDEVINFO, dev_data: &SP_DEVINFO_DATA) -> bool {
+    let mut ids = [0u16; 256];
+    let ok = unsafe {
+        SetupDiGetDeviceRegistryPropertyW(
+            dev_info,
+            dev_data,
+            SPDRP_HARDWAREID,
+            std::ptr::null_mut(),
+            ids.as_mut_ptr() as *mut u8,
+            std::mem::size_of_val(&ids) as u32,
+            std::ptr::null_mut(),
+        )
+    };
+    //The ids are a list of strings ending with an empty string
+    ok != FALSE
+        && ids
+            .split(|c| *c == 0)
+            .take_while(|id| !id.is_empty())
+            .any(|id| String::from_utf16_lossy(id).eq_ignore_ascii_case(WINTUN_HARDWARE_ID))
+}
+
+/// Removes the wintun network device whose NetCfgInstanceId is `guid`, the same way
+/// WintunCloseAdapter removes adapters it created. Returns false if no such device is present, and
+/// an error if the device exists but was not created by the wintun driver
+pub(crate) fn remove_net_device(guid: &GUID) -> std::io::Result<bool> {
+    let target = guid_to_win_style_string(guid).map_err(std::io::Error::from)?;
+
+    let dev_info = unsafe { SetupDiGetClassDevsW(&GUID_DEVCLASS_NET, std::ptr::null(), 0, DIGCF_PRESENT) };
+    if dev_info == INVALID_HANDLE_VALUE {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    let mut result = Ok(false);
+    for index in 0.. {
+        let mut dev_data: SP_DEVINFO_DATA = unsafe { std::mem::zeroed() };
+        dev_data.cbSize = std::mem::size_of::<SP_DEVINFO_DATA>() as u32;
+        if FALSE == unsafe { SetupDiEnumDeviceInfo(dev_info, index, &mut dev_data) } {
+            match unsafe { GetLastError() } {
+                ERROR_NO_MORE_ITEMS => break,
+                _ => continue,
+            }
+        }
+
+        //SAFETY: dev_data was just filled in by SetupDiEnumDeviceInfo for this dev_info set
+        let key = unsafe { SetupDiOpenDevRegKey(dev_info, &dev_data, DICS_FLAG_GLOBAL, 0, DIREG_DRV, KEY_QUERY_VALUE) };
+        if key == INVALID_HANDLE_VALUE {
+            continue;
+        }
+        let value_name: Vec<u16> = "NetCfgInstanceId".encode_utf16().chain(std::iter::once(0)).collect();
+        let mut instance_id = [0u16; 40];
+        let mut size = std::mem::size_of_val(&instance_id) as u32;
+        let status = unsafe {
+            RegQueryValueExW(
+                key,
+                value_name.as_ptr(),
+                std::ptr::null(),
+                std::ptr::null_mut(),
+                instance_id.as_mut_ptr() as *mut u8,
+                &mut size,
+            )
+        };
+        unsafe { RegCloseKey(key) };
+        if status != ERROR_SUCCESS || !decode_utf16(&instance_id).eq_ignore_ascii_case(&target) {
+            continue;
+        }
+        if !is_wintun_device(dev_info, &dev_data) {
+            let msg = format!("Device {} is not a wintun adapter", target);
+            result = Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, msg));
+            break;
+        }
+
+        let params = SP_REMOVEDEVICE_PARAMS {
+            ClassInstallHeader: SP_CLASSINSTALL_HEADER {
+                cbSize: std::mem::size_of::<SP_CLASSINSTALL_HEADER>() as u32,
+                InstallFunction: DIF_REMOVE,
+            },
+            Scope: DI_REMOVEDEVICE_GLOBAL,
+            HwProfile: 0,
+        };
+        let removed = unsafe {
+            FALSE
+                != SetupDiSetClassInstallParamsW(
+                    dev_info,
+                    &dev_data,
+                    &params.ClassInstallHeader,
+                    std::mem::size_of_val(&params) as u32,
+                )
+                && FALSE != SetupDiCallClassInstaller(DIF_REMOVE, dev_info, &dev_data)
+        };
+        result = match removed {
+            true => Ok(true),
+            false => Err(std::io::Error::last_os_error()),
+        };
+        break;
+    }
+
+    unsafe { SetupDiDestroyDeviceInfoList(dev_info) };
+    result
 }
 
 pub fn decode_utf16(string: &[u16]) -> String {
     let end = string.iter().position(|b| *b == 0).unwrap_or(string.len());
     String::from_utf16_lossy(&string[..end])
 }
-
-#[repr(C, align(1))]
-#[derive(c2rust_bitfields::BitfieldStruct)]
-#[allow(non_snake_case)]
-#[allow(non_camel_case_types)]
-struct _NET_LUID_LH_INFO {
-    #[bitfield(name = "Reserved", ty = "u64", bits = "0..=23")]
-    #[bitfield(name = "NetLuidIndex", ty = "u64", bits = "24..=47")]
-    #[bitfield(name = "IfType", ty = "u64", bits = "48..=63")]
-    _Value: [u8; 8],
-}
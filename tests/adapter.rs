@@ -0,0 +1,104 @@
+mod common;
+
+#[test]
+#[ignore = "needs administrator rights and the wintun driver"]
+fn test_delete_by_name() {
+    let wintun = common::load();
+    let name = "WintunTestDeleteByName";
+    let adapter = common::create_adapter(&wintun, name);
+    //Leave the adapter behind like a process that crashed before closing it
+    std::mem::forget(adapter);
+
+    assert!(wintun::Adapter::delete_by_name(&wintun, name).unwrap());
+    assert!(!wintun::Adapter::delete_by_name(&wintun, name).unwrap());
+    assert!(wintun::Adapter::open(&wintun, name).is_err());
+
+    //Interfaces that wintun did not create are left alone
+    assert!(!wintun::Adapter::delete_by_name(&wintun, "Loopback Pseudo-Interface 1").unwrap());
+}
+
+#[test]
+#[ignore = "needs administrator rights and the wintun driver"]
+fn test_create_with_fixed_guids() {
+    let wintun = common::load();
+    let first_guid = 0x8f4c_2a63_1d7e_4b90_a5c3_6e21_f0d8_9b01;
+    let second_guid = 0x8f4c_2a63_1d7e_4b90_a5c3_6e21_f0d8_9b02;
+    let first = wintun::Adapter::create(&wintun, "WintunTestGuid1", common::TUNNEL_TYPE, Some(first_guid)).unwrap();
+    let second = wintun::Adapter::create(&wintun, "WintunTestGuid2", common::TUNNEL_TYPE, Some(second_guid)).unwrap();
+    assert_eq!(first.get_guid(), first_guid);
+    assert_eq!(second.get_guid(), second_guid);
+
+    //The guid is kept when the adapter is opened again
+    let opened = wintun::Adapter::open(&wintun, "WintunTestGuid2").unwrap();
+    assert_eq!(opened.get_guid(), second_guid);
+}
+
+#[test]
+#[ignore = "needs administrator rights and the wintun driver"]
+fn test_second_session_is_rejected() {
+    let wintun = common::load();
+    let adapter = common::create_adapter(&wintun, "WintunTestDoubleStart");
+    let session = adapter.start_session(wintun::MIN_RING_CAPACITY).unwrap();
+    assert!(matches!(
+        adapter.start_session(wintun::MIN_RING_CAPACITY),
+        Err(wintun::Error::SessionAlreadyActive)
+    ));
+
+    //Ending the first session allows starting another one
+    drop(session);
+    adapter.start_session(wintun::MIN_RING_CAPACITY).unwrap();
+}
+
+#[test]
+#[ignore = "needs administrator rights and the wintun driver"]
+fn test_session_outlives_dropped_adapter() {
+    let wintun = common::load();
+    let name = "WintunTestDropOrder";
+    let adapter = common::create_adapter(&wintun, name);
+    let session = std::sync::Arc::new(adapter.start_session(wintun::MIN_RING_CAPACITY).unwrap());
+
+    //Dropping the adapter first must not close it while the session still uses it
+    drop(adapter);
+    assert_eq!(session.get_adapter().get_name().unwrap(), name);
+    assert!(session.try_receive().is_ok());
+    session.send_slice(&[0u8; 20]).unwrap();
+
+    //The adapter is closed, and removed since we created it, once the session is gone
+    drop(session);
+    assert!(wintun::Adapter::open(&wintun, name).is_err());
+}
+
+#[test]
+#[ignore = "needs administrator rights and the wintun driver"]
+fn test_rename() {
+    let wintun = common::load();
+    let adapter = common::create_adapter(&wintun, "WintunTestRename");
+    let other = common::create_adapter(&wintun, "WintunTestRenameOther");
+
+    adapter.rename("WintunTestRenamed").unwrap();
+    assert_eq!(adapter.get_name().unwrap(), "WintunTestRenamed");
+    let opened = wintun::Adapter::open_by_alias(&wintun, "WintunTestRenamed").unwrap();
+    assert_eq!(opened.get_guid(), adapter.get_guid());
+
+    let err = adapter.rename(&other.get_name().unwrap()).unwrap_err();
+    assert!(matches!(err, wintun::Error::NameInUse(_)), "Unexpected error: {}", err);
+    assert_eq!(adapter.get_name().unwrap(), "WintunTestRenamed");
+}
+
+#[test]
+#[ignore = "needs administrator rights and the wintun driver"]
+fn test_scoped_ipv4_leaves_existing_address() {
+    let wintun = common::load();
+    let adapter = common::create_adapter(&wintun, "WintunTestScopedIpv4");
+    let existing = std::net::Ipv4Addr::new(10, 28, 26, 1);
+    adapter.set_ipv4(existing, 24).unwrap();
+
+    assert!(adapter.scoped_ipv4(existing, 24).is_err());
+    assert!(adapter.get_addresses().unwrap().contains(&existing.into()));
+
+    let scoped = std::net::Ipv4Addr::new(10, 28, 26, 2);
+    drop(adapter.scoped_ipv4(scoped, 24).unwrap());
+    let addresses = adapter.get_addresses().unwrap();
+    assert!(addresses.contains(&existing.into()));
+    assert!(!addresses.contains(&scoped.into()));
+}
@@ -0,0 +1,111 @@
+#![cfg(feature = "tokio")]
+
+mod common;
+
+use std::{
+    net::{Ipv4Addr, UdpSocket},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use wintun::AsyncSession;
+
+const PAYLOAD: &[u8] = b"wintun async test";
+
+/// Returns true if `packet` is an IPv4 UDP datagram to `dest` carrying [`PAYLOAD`]
+fn is_test_datagram(packet: &[u8], dest: Ipv4Addr) -> bool {
+    packet.len() >= 28
+        && packet[0] >> 4 == 4
+        && packet[9] == 17
+        && packet[16..20] == dest.octets()
+        && packet.ends_with(PAYLOAD)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "needs administrator rights and the wintun driver"]
+async fn test_recv_returns_routed_datagram() {
+    let wintun = common::load();
+    let adapter = common::create_adapter(&wintun, "WintunTestAsyncRecv");
+    adapter.set_ipv4(Ipv4Addr::new(10, 28, 21, 1), 24).unwrap();
+    let session = AsyncSession::from(adapter.start_session(wintun::MAX_RING_CAPACITY).unwrap());
+
+    //Datagrams to another address in the subnet are routed into the tunnel. The address may take a
+    //moment to become usable, so keep sending until one arrives
+    let dest = Ipv4Addr::new(10, 28, 21, 2);
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).unwrap();
+    let received = tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            let _ = socket.send_to(PAYLOAD, (dest, 9));
+            //Also exercises cancelling recv while it waits on the blocking pool
+            if let Ok(packet) = tokio::time::timeout(Duration::from_millis(200), session.recv()).await {
+                if is_test_datagram(&packet.unwrap(), dest) {
+                    break;
+                }
+            }
+        }
+    })
+    .await;
+    assert!(received.is_ok(), "No datagram was received through the tunnel");
+
+    session.shutdown().unwrap();
+    assert!(matches!(session.recv().await, Err(wintun::Error::ShuttingDown)));
+}
+
+#[test]
+#[ignore = "needs administrator rights and the wintun driver"]
+fn test_cancelled_recv_releases_session() {
+    let wintun = common::load();
+    let adapter = common::create_adapter(&wintun, "WintunTestAsyncCancel");
+    let session = Arc::new(adapter.start_session(wintun::MAX_RING_CAPACITY).unwrap());
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_time()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let async_session = AsyncSession::new(session.clone());
+        for _ in 0..16 {
+            let _ = tokio::time::timeout(Duration::from_millis(10), async_session.recv()).await;
+        }
+    });
+
+    //Every cancelled wait drops its reference once the blocking thread returns
+    let start = Instant::now();
+    while Arc::strong_count(&session) > 1 {
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "Cancelled receives still hold the session"
+        );
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    runtime.shutdown_timeout(Duration::from_secs(1));
+}
+
+#[test]
+#[ignore = "needs administrator rights and the wintun driver"]
+fn test_dropped_stream_releases_session() {
+    use futures_core::Stream;
+
+    let wintun = common::load();
+    let adapter = common::create_adapter(&wintun, "WintunTestAsyncStream");
+    let session = Arc::new(adapter.start_session(wintun::MAX_RING_CAPACITY).unwrap());
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_time()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let mut stream = AsyncSession::new(session.clone()).into_stream();
+        let next = std::future::poll_fn(|cx| std::pin::Pin::new(&mut stream).poll_next(cx));
+        let _ = tokio::time::timeout(Duration::from_millis(10), next).await;
+    });
+
+    let start = Instant::now();
+    while Arc::strong_count(&session) > 1 {
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "The dropped stream still holds the session"
+        );
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    runtime.shutdown_timeout(Duration::from_secs(1));
+}
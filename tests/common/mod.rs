@@ -0,0 +1,29 @@
+//! Setup shared by the driver tests. They create real adapters, so they need administrator rights
+//! and the wintun driver, and are ignored by default. Run them from an elevated prompt with
+//! `cargo test --all-features -- --ignored`
+#![allow(dead_code)]
+
+use std::sync::Arc;
+
+/// Tunnel type of every adapter created by the tests
+pub const TUNNEL_TYPE: &str = "WintunTests";
+
+/// Loads the wintun.dll shipped in this repository for the current architecture
+pub fn load() -> wintun::Wintun {
+    let arch = if cfg!(target_arch = "x86") {
+        "x86"
+    } else if cfg!(target_arch = "x86_64") {
+        "amd64"
+    } else if cfg!(target_arch = "arm") {
+        "arm"
+    } else {
+        "arm64"
+    };
+    let path = format!("{}/wintun/bin/{}/wintun.dll", env!("CARGO_MANIFEST_DIR"), arch);
+    unsafe { wintun::load_from_path(path) }.expect("Failed to load wintun.dll")
+}
+
+/// Creates an adapter named `name`. Every test uses its own name, so tests can run in parallel
+pub fn create_adapter(wintun: &wintun::Wintun, name: &str) -> Arc<wintun::Adapter> {
+    wintun::Adapter::create(wintun, name, TUNNEL_TYPE, None).expect("Failed to create adapter")
+}
@@ -0,0 +1,222 @@
+mod common;
+
+use std::{
+    io::Write,
+    net::{Ipv4Addr, UdpSocket},
+    sync::Arc,
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+use windows_sys::Win32::System::Threading::{GetCurrentProcess, GetProcessHandleCount};
+
+fn process_handle_count() -> u32 {
+    let mut count = 0;
+    assert_ne!(unsafe { GetProcessHandleCount(GetCurrentProcess(), &mut count) }, 0);
+    count
+}
+
+/// Payload of the datagrams the tests send into the tunnel, to tell them apart from other traffic
+const MARKER: &[u8] = b"wintun session test";
+
+fn is_test_datagram(packet: &[u8]) -> bool {
+    packet.len() >= 28 && packet[0] >> 4 == 4 && packet[9] == 17 && packet.ends_with(MARKER)
+}
+
+/// Gives `session` the address `.1` in `subnet` and waits until datagrams sent to `.2` arrive in
+/// the tunnel, which may take a moment after the address was added. Returns the `.2` address
+fn route_into(session: &Arc<wintun::Session>, subnet: [u8; 3]) -> Ipv4Addr {
+    let [a, b, c] = subnet;
+    session.get_adapter().set_ipv4(Ipv4Addr::new(a, b, c, 1), 24).unwrap();
+    let dest = Ipv4Addr::new(a, b, c, 2);
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).unwrap();
+    let start = Instant::now();
+    loop {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "No datagram was routed into the tunnel"
+        );
+        let _ = socket.send_to(MARKER, (dest, 9));
+        if let Some(packet) = session.receive_blocking_timeout(Duration::from_millis(200)).unwrap() {
+            if is_test_datagram(&packet) {
+                break;
+            }
+        }
+    }
+    //Drop the remaining probes so they are not counted by the test
+    session.flush_received().unwrap();
+    dest
+}
+
+/// Sends `count` test datagrams to `dest` from another thread
+fn spawn_sender(dest: Ipv4Addr, count: usize) -> JoinHandle<()> {
+    std::thread::spawn(move || {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).unwrap();
+        for _ in 0..count {
+            socket.send_to(MARKER, (dest, 9)).unwrap();
+        }
+    })
+}
+
+/// Packets as large as wintun allows, so a ring of [`wintun::MIN_RING_CAPACITY`] holds only a few
+const LARGE_PACKET: usize = 0xffff;
+
+#[test]
+#[ignore = "needs administrator rights and the wintun driver"]
+fn test_full_ring_is_would_block() {
+    let wintun = common::load();
+    let adapter = common::create_adapter(&wintun, "WintunTestWouldBlock");
+    let mut session = adapter.start_session(wintun::MIN_RING_CAPACITY).unwrap();
+
+    //Garbage the stack discards, but it still has to pass through the send ring
+    let packet = vec![0u8; LARGE_PACKET];
+    let would_block = (0..100_000).any(|_| match session.write(&packet) {
+        Ok(_) => false,
+        Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => true,
+        Err(err) => panic!("Unexpected write error: {}", err),
+    });
+    assert!(would_block, "The send ring never filled up");
+}
+
+#[test]
+#[ignore = "needs administrator rights and the wintun driver"]
+fn test_sessions_do_not_leak_handles() {
+    let wintun = common::load();
+    let adapter = common::create_adapter(&wintun, "WintunTestHandleLeak");
+    //The first session lets the driver and the dll open whatever they keep around
+    drop(adapter.start_session(wintun::MIN_RING_CAPACITY).unwrap());
+
+    let before = process_handle_count();
+    for _ in 0..200 {
+        let session = adapter.start_session(wintun::MIN_RING_CAPACITY).unwrap();
+        session.get_read_wait_event().unwrap();
+        session.shutdown().unwrap();
+    }
+    let after = process_handle_count();
+    //Leave some slack for handles opened by other threads of the test harness
+    assert!(after <= before + 10, "Handle count grew from {} to {}", before, after);
+}
+
+#[test]
+#[ignore = "needs administrator rights and the wintun driver"]
+fn test_full_ring_is_ring_full() {
+    let wintun = common::load();
+    let adapter = common::create_adapter(&wintun, "WintunTestRingFull");
+    let session = std::sync::Arc::new(adapter.start_session(wintun::MIN_RING_CAPACITY).unwrap());
+
+    //Hold on to the allocated packets so the ring cannot drain while it is filled
+    let mut packets = vec![];
+    let err = loop {
+        match session.allocate_send_packet(LARGE_PACKET as u16) {
+            Ok(packet) => packets.push(packet),
+            Err(err) => break err,
+        }
+        assert!(packets.len() < 64, "The send ring never filled up");
+    };
+    assert!(matches!(err, wintun::Error::RingFull), "Unexpected error: {}", err);
+    assert_eq!(std::io::Error::from(err).kind(), std::io::ErrorKind::WouldBlock);
+
+    //Sending the held packets makes room again
+    for packet in packets {
+        session.send_packet(packet);
+    }
+}
+
+#[test]
+#[ignore = "needs administrator rights and the wintun driver"]
+fn test_prompt_drops_keep_ring_from_filling() {
+    const COUNT: usize = 20_000;
+    let wintun = common::load();
+    let adapter = common::create_adapter(&wintun, "WintunTestPromptDrop");
+    //Far more datagrams than the smallest ring holds at once
+    let session = Arc::new(adapter.start_session(wintun::MIN_RING_CAPACITY).unwrap());
+    let dest = route_into(&session, [10, 28, 23]);
+
+    let sender = spawn_sender(dest, COUNT);
+    let mut received = 0;
+    while received < COUNT {
+        let Some(packet) = session.receive_blocking_timeout(Duration::from_secs(2)).unwrap() else {
+            break;
+        };
+        if is_test_datagram(&packet) {
+            received += 1;
+        }
+        //Dropping the packet right away frees its slot in the ring
+    }
+    sender.join().unwrap();
+    assert_eq!(received, COUNT, "The ring filled up and the driver dropped packets");
+    assert_eq!(session.approx_pending().unwrap(), 0);
+}
+
+#[test]
+#[ignore = "needs administrator rights and the wintun driver"]
+fn test_iter_collects_sent_packets_until_shutdown() {
+    const COUNT: usize = 1000;
+    let wintun = common::load();
+    let adapter = common::create_adapter(&wintun, "WintunTestIter");
+    let session = Arc::new(adapter.start_session(wintun::MAX_RING_CAPACITY).unwrap());
+    let dest = route_into(&session, [10, 28, 24]);
+
+    //Other traffic keeps the ring busy, so the iterator must stop on the shutdown alone
+    let stop = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let noise = {
+        let stop = stop.clone();
+        std::thread::spawn(move || {
+            let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).unwrap();
+            while !stop.load(std::sync::atomic::Ordering::Relaxed) {
+                let _ = socket.send_to(b"noise", (dest, 9));
+            }
+        })
+    };
+    let sender = spawn_sender(dest, COUNT);
+
+    let mut received = 0;
+    for packet in session.iter() {
+        if is_test_datagram(&packet.unwrap()) {
+            received += 1;
+            if received == COUNT {
+                session.shutdown().unwrap();
+            }
+        }
+    }
+    stop.store(true, std::sync::atomic::Ordering::Relaxed);
+    sender.join().unwrap();
+    noise.join().unwrap();
+    assert_eq!(received, COUNT);
+}
+
+#[test]
+#[ignore = "needs administrator rights and the wintun driver"]
+fn test_raw_session_keeps_adapter_and_session() {
+    let wintun = common::load();
+    let adapter = common::create_adapter(&wintun, "WintunTestRawSession");
+    let session = adapter.start_session(wintun::MIN_RING_CAPACITY).unwrap();
+    drop(adapter);
+
+    let (handle, adapter) = session.into_raw();
+    assert!(matches!(
+        adapter.start_session(wintun::MIN_RING_CAPACITY),
+        Err(wintun::Error::SessionAlreadyActive)
+    ));
+    let session = unsafe { wintun::Session::from_raw(&adapter, handle, wintun::MIN_RING_CAPACITY) }.unwrap();
+    let mut buf = [0; LARGE_PACKET];
+    session.try_receive_into(&mut buf).unwrap();
+    drop(session);
+    drop(adapter.start_session(wintun::MIN_RING_CAPACITY).unwrap());
+}
+
+#[test]
+#[ignore = "needs administrator rights and the wintun driver"]
+fn test_multi_session_shutdown_beats_ready_session() {
+    let wintun = common::load();
+    let adapter = common::create_adapter(&wintun, "WintunTestMultiShutdown");
+    let session = Arc::new(adapter.start_session(wintun::MIN_RING_CAPACITY).unwrap());
+    let dest = route_into(&session, [10, 28, 25]);
+    spawn_sender(dest, 1).join().unwrap();
+
+    let multi = wintun::MultiSession::new(vec![session.clone()]).unwrap();
+    assert_eq!(multi.wait(Some(Duration::from_secs(2))).unwrap(), Some(0));
+    //A peeked packet keeps the session ready without the driver signaling it again
+    assert!(session.peek(|_| ()).unwrap().is_some());
+    multi.shutdown().unwrap();
+    assert!(matches!(multi.wait(None), Err(wintun::Error::ShuttingDown)));
+}